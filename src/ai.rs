@@ -1,13 +1,34 @@
-use std::cmp::{max, min};
+use std::cmp::{max, min, Reverse};
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
 
 use ahash::AHashMap;
 
+use crate::book::Book;
+use crate::difficulty::Difficulty;
+use crate::engine::{
+    check_playable, Assessment, Engine, Limits, MoveError, MoveSource, SearchInfo, StopHandle,
+};
+use crate::eval::{winning_cells, Evaluate, Heuristic, Noisy, MAX_EVAL};
+use crate::explain::Explanation;
 use crate::packedboard::*;
+use crate::rng::Rng;
+use crate::solved::invalid_data;
+use crate::tablebase::Tablebase;
 
 const MAX_DEPTH: u8 = 20;
+/// Depth of the search guessing the reply of the opponent while pondering.
+const PONDER_PREDICTION_DEPTH: u8 = 8;
 
-const PLAYER_COLOR: NonEmptySqrState = NonEmptySqrState::Red;
-const AI_COLOR: NonEmptySqrState = NonEmptySqrState::Yellow;
+pub const PLAYER_COLOR: NonEmptySqrState = NonEmptySqrState::Red;
+pub const AI_COLOR: NonEmptySqrState = NonEmptySqrState::Yellow;
 
 const COLS_ORDER: [ColIdx; NCOL as usize] = [
     ALL_COL_IDXS[3],
@@ -20,21 +41,77 @@ const COLS_ORDER: [ColIdx; NCOL as usize] = [
 ];
 
 #[derive(Debug, PartialEq, PartialOrd, Eq, Ord, Clone, Copy)]
-struct Score(i8);
+pub struct Score(i16);
 
 impl Score {
-    fn new(x: i8) -> Self {
+    pub(crate) fn new(x: i16) -> Self {
         Self(x)
     }
 
-    fn get(self) -> i8 {
+    pub fn get(self) -> i16 {
         self.0
     }
+
+    /// Score of a win with `remaining_tokens` tokens of the winner still
+    /// unplayed, so quicker wins score higher.
+    pub(crate) fn win(remaining_tokens: i8) -> Self {
+        Self(Self::PROVEN + remaining_tokens as i16)
+    }
+
+    /// Score of a static evaluation, see [`Evaluate`].
+    fn heuristic(eval: i16) -> Self {
+        Self(eval.clamp(-MAX_EVAL, MAX_EVAL))
+    }
+
+    /// Number of tokens the winner has played once it has won, if the score
+    /// is a proven win or loss.
+    pub fn winner_tokens(self) -> Option<u8> {
+        if self.is_proven() {
+            Some((22 - (self.0.abs() - Self::PROVEN)) as u8)
+        } else {
+            None
+        }
+    }
+
+    /// Result of the game, for a score obtained without hitting the search
+    /// horizon.
+    fn outcome(self) -> Outcome {
+        match self.0 {
+            0 => Outcome::Draw,
+            x if x > 0 => Outcome::Win,
+            _ => Outcome::Loss,
+        }
+    }
+
+    /// The score stored as `raw` by [`Score::get`], if it is a valid one.
+    pub(crate) fn from_raw(raw: i16) -> Option<Self> {
+        if raw.abs() <= Self::win(21).get() {
+            Some(Self(raw))
+        } else {
+            None
+        }
+    }
+
+    fn next(self) -> Self {
+        Self(self.0 + 1)
+    }
+
+    fn prev(self) -> Self {
+        Self(self.0 - 1)
+    }
+
+    /// Whether the score is a forced win or loss rather than an estimate.
+    pub fn is_proven(self) -> bool {
+        self.0.abs() > Self::PROVEN
+    }
 }
 
 impl Score {
-    const MAX: Self = Self(i8::MAX);
-    const MIN: Self = Self(i8::MIN + 1);
+    const MAX: Self = Self(i16::MAX);
+    const MIN: Self = Self(i16::MIN + 1);
+    /// Proven results are stored beyond this value, static evaluations
+    /// strictly within it.
+    const PROVEN: i16 = MAX_EVAL + 1;
 }
 
 impl std::ops::Neg for Score {
@@ -51,48 +128,149 @@ impl std::fmt::Display for Score {
         if inner == 0 {
             return f.write_fmt(format_args!("<unknown_issue>"));
         }
+        if !self.is_proven() {
+            return f.write_fmt(format_args!("<unknown_issue> (evaluation: {})", inner));
+        }
         f.write_fmt(format_args!(
             "{} win at turn n°{}",
             if inner > 0 { "I" } else { "you" },
-            22 - inner.abs()
+            self.winner_tokens().unwrap()
         ))
     }
 }
 
+/// In which order `negamax` tries the columns of a position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveOrdering {
+    /// Always [`COLS_ORDER`], from the center outwards.
+    Fixed,
+    /// The cached best move first, then the moves creating the most
+    /// winning cells, then killer moves and finally the history heuristic.
+    Dynamic,
+}
+
+/// Which moves caused cutoffs in earlier searches, to try them early.
+struct History {
+    /// Indexed by color then by cell in the [`Board::bitboard`] layout.
+    cutoffs: [[u32; 64]; 2],
+    /// Last move that caused a cutoff at each turn.
+    killers: [Option<ColIdx>; 43],
+}
+
+impl History {
+    fn new() -> Self {
+        Self {
+            cutoffs: [[0; 64]; 2],
+            killers: [None; 43],
+        }
+    }
+
+    fn cutoff(&mut self, color: NonEmptySqrState, cell: u8, turn: u8, col: ColIdx, depth: u8) {
+        let counter = &mut self.cutoffs[color as usize][cell as usize];
+        *counter = counter.saturating_add(depth as u32 * depth as u32);
+        self.killers[turn as usize] = Some(col);
+    }
+}
+
+/// Everything the search reads or updates besides the position itself.
+struct Search<'a> {
+    cache: &'a Cache,
+    eval: &'a dyn Evaluate,
+    history: &'a mut History,
+    ordering: MoveOrdering,
+    /// Number of `negamax` calls so far.
+    nodes: u64,
+    /// Cached positions met, and how many of them needed no search.
+    tt_hits: u64,
+    tt_cutoffs: u64,
+    /// Set once the result of the search is no longer needed. A stopped
+    /// `negamax` returns a meaningless score and caches nothing.
+    stop: &'a AtomicBool,
+    /// Like `stop`, but set from outside the search, see [`Engine::stop_handle`].
+    cancel: &'a AtomicBool,
+    /// The search stops by itself at that time.
+    deadline: Option<Instant>,
+    /// Offset into [`COLS_ORDER`] for the first column to try, so that
+    /// parallel searches explore the tree in different orders.
+    rotation: usize,
+    /// Exact values of the endgames, instead of searching them.
+    tablebase: Option<&'a Tablebase>,
+}
+
+impl Search<'_> {
+    fn stopped(&self) -> bool {
+        if let Some(deadline) = self.deadline {
+            // Reading the clock is not free, do it every so often.
+            if self.nodes.is_multiple_of(1024) && Instant::now() >= deadline {
+                self.stop.store(true, Ordering::Relaxed);
+            }
+        }
+        self.stop.load(Ordering::Relaxed) || self.cancel.load(Ordering::Relaxed)
+    }
+}
+
+/// A move winning right away for the player about to move in `b`.
+fn winning_move(b: &Board) -> Option<ColIdx> {
+    ALL_COL_IDXS
+        .iter()
+        .copied()
+        .find(|&coli| matches!(b.clone().add_and_check(coli, b.to_play()), Ok(true)))
+}
+
+/// Cell where the next token of `coli` lands, in the [`Board::bitboard`]
+/// layout.
+fn landing_cell(b: &Board, coli: ColIdx) -> u8 {
+    coli.get() * BITBOARD_HEIGHT + b.col_len(coli)
+}
+
 fn negamax(
     b: &Board,
     tree_depth: u8,
     turn: u8,
     color: NonEmptySqrState,
-    cache: &mut Cache,
+    search: &mut Search,
     mut alpha: Score,
-    mut beta: Score,
+    beta: Score,
 ) -> (Option<ColIdx>, Score) {
-    let remaining_tokens_p1 = Score::new(22 - (((turn + 1) / 2) as i8));
-    if tree_depth == 0 || turn == 42 {
-        return (None, Score::new(0))
-    }
-    if let Some(&cachentry) = cache.result.get(&b) {
-        match cachentry {
-            CacheEntry::Exact(col, score) => return (Some(col),score),
-            CacheEntry::Unknown { turn: cache_turn } => {
-                // dbg!(turn,tree_depth,cache_turn);
-                if turn + tree_depth <= cache_turn {
-                    return (None, Score::new(0));
-                }
+    search.nodes += 1;
+    if search.stopped() {
+        return (None, Score::new(0));
+    }
+    let remaining_tokens_p1 = Score::win(22 - turn.div_ceil(2) as i8);
+    if turn > GRID_SIZE {
+        return (None, Score::new(0));
+    }
+    if let Some((col, score)) = search.tablebase.and_then(|tablebase| tablebase.lookup(b)) {
+        return (Some(col), score);
+    }
+    if tree_depth == 0 {
+        return (None, Score::heuristic(search.eval.evaluate(b, color)));
+    }
+    // A bound only ends the search when it falls outside the window: the
+    // window is not narrowed to it, so that a result within the window
+    // asked for is exact and the principal variation can follow it.
+    let alpha_orig = alpha;
+    let cached = search.cache.probe(b);
+    if let Some(entry) = cached {
+        search.tt_hits += 1;
+        if entry.depth >= tree_depth {
+            let cutoff = match entry.bound {
+                Bound::Exact => true,
+                Bound::Lower => entry.score >= beta,
+                Bound::Upper => entry.score <= alpha,
+            };
+            if cutoff {
+                search.tt_cutoffs += 1;
+                return (Some(entry.col), entry.score);
             }
         }
     }
-    if let Some(&lb) = cache.lower_bounds.get(&b) {
-                if lb >= beta {return (None, lb)};
-                alpha = max(alpha, lb);
-            }
-        
-    
-    let (res_col,res_score) = {
+
+    let (res_col, res_score) = {
         let mut recurse_positions = Vec::new();
-        for &candidate_col in &COLS_ORDER {
-            let mut b1 = (*b).clone();
+        for i in 0..COLS_ORDER.len() {
+            let candidate_col = COLS_ORDER[(i + search.rotation) % COLS_ORDER.len()];
+            let mut b1 = b.clone();
             match b1.add_and_check(candidate_col, color) {
                 Ok(true) => {
                     return (Some(candidate_col), remaining_tokens_p1);
@@ -102,20 +280,38 @@ fn negamax(
                 Err(e) => unreachable!("{}", e),
             }
         }
+        if search.ordering == MoveOrdering::Dynamic {
+            let own = b.bitboard(color);
+            let occupied = b.occupied_bitboard();
+            let cached_col = cached.map(|entry| entry.col);
+            let killer = search.history.killers[turn as usize];
+            let cutoffs = &search.history.cutoffs[color as usize];
+            recurse_positions.sort_by_cached_key(|&(col, _)| {
+                let cell = 1 << landing_cell(b, col);
+                let threats = winning_cells(own | cell, occupied | cell).count_ones();
+                Reverse((
+                    cached_col == Some(col),
+                    threats,
+                    killer == Some(col),
+                    cutoffs[landing_cell(b, col) as usize],
+                ))
+            });
+        }
         let mut current_best_candidate = ALL_COL_IDXS[0];
         let mut current_best = Score::MIN;
-        // println!("Starting: {} <= {}", alpha, beta);
         for (candidate_col, pos) in recurse_positions.into_iter() {
-            // dbg!(alpha,beta);
             let (_, neg_score) = negamax(
                 &pos,
                 tree_depth - 1,
                 turn + 1,
                 color.other(),
-                cache,
+                search,
                 -beta,
                 -alpha,
             );
+            if search.stopped() {
+                return (None, Score::new(0));
+            }
             let score = -neg_score;
             if score > current_best {
                 current_best_candidate = candidate_col;
@@ -123,83 +319,1129 @@ fn negamax(
             }
             alpha = max(alpha, score);
             if alpha >= beta {
-                // println!("Pruning: {} >= {}", alpha, beta);
+                let cell = landing_cell(b, candidate_col);
+                search
+                    .history
+                    .cutoff(color, cell, turn, candidate_col, tree_depth);
                 break;
             }
         }
-        (Some(current_best_candidate), current_best)
+        (current_best_candidate, current_best)
+    };
+    let bound = if res_score <= alpha_orig {
+        Bound::Upper
+    } else if res_score >= beta {
+        Bound::Lower
+    } else {
+        Bound::Exact
     };
-    if alpha < beta {
-        if res_score.get() == 0 {
-            cache.result.insert(b.clone(), CacheEntry::Unknown{turn});
+    search.cache.store(
+        b,
+        CacheEntry {
+            col: res_col,
+            score: res_score,
+            bound,
+            depth: tree_depth,
+        },
+    );
+    (Some(res_col), res_score)
+}
+
+/// The scores `b` may have once solved, in increasing order: a win of the
+/// player to move with one of its remaining tokens, a loss to one of the
+/// opponent's, or a draw.
+fn possible_scores(turn: u8) -> Vec<Score> {
+    let mut scores: Vec<Score> = (turn..=GRID_SIZE)
+        .map(|t| {
+            let win = Score::win(22 - t.div_ceil(2) as i8);
+            if (t - turn).is_multiple_of(2) {
+                win
+            } else {
+                -win
+            }
+        })
+        .chain(std::iter::once(Score::new(0)))
+        .collect();
+    scores.sort_unstable();
+    scores
+}
+
+/// Solves `b` with a sequence of null-window `negamax` calls down to the end
+/// of the game, bisecting the [possible scores](possible_scores) rather than
+/// every value a score can take. The first call tells whether `b` is won,
+/// and the cache keeps the bounds found by each call for the next ones.
+fn null_window_search(
+    b: &Board,
+    turn: u8,
+    color: NonEmptySqrState,
+    search: &mut Search,
+) -> (Option<ColIdx>, Score) {
+    let tree_depth = GRID_SIZE + 1 - turn;
+    let scores = possible_scores(turn);
+    // The value is one of `scores[lower..=upper]`.
+    let (mut lower, mut upper) = (0, scores.len() - 1);
+    let mut probe = scores.binary_search(&Score::new(0)).unwrap();
+    while lower < upper {
+        // Whether the value is above `scores[probe]`.
+        let mid = scores[probe];
+        let (_, score) = negamax(b, tree_depth, turn, color, search, mid, mid.next());
+        if search.stopped() {
+            return (None, Score::new(0));
+        }
+        if score <= mid {
+            upper = scores.partition_point(|&s| s <= score) - 1;
         } else {
-            cache.result.insert(b.clone(), CacheEntry::Exact(res_col.unwrap(), res_score));
+            lower = scores.partition_point(|&s| s < score);
         }
+        probe = lower + (upper - lower) / 2;
     }
-    else if res_score.get() != 0 {
-        cache.lower_bounds.entry(b.clone()).and_modify(|lb| *lb=min(*lb,res_score)).or_insert(res_score);
+    // Once more with the value alone in the window, for a move reaching it
+    // and exact entries in the cache along the principal variation.
+    let value = scores[lower];
+    negamax(
+        b,
+        tree_depth,
+        turn,
+        color,
+        search,
+        value.prev(),
+        value.next(),
+    )
+}
+
+/// How `AI` looks for the value of a position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Solver {
+    /// A single `negamax` call with the widest possible window.
+    FullWindow,
+    /// Like `FullWindow` when the depth is limited, but solves exactly with
+    /// repeated null-window `negamax` calls, see [`null_window_search`]. The
+    /// default.
+    NullWindow,
+    /// A single `negamax` call with the window `[-1, 1]`: quicker, but only
+    /// tells wins, draws and losses apart.
+    Weak,
+}
+
+/// Game-theoretic result for the player about to move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Outcome {
+    Win,
+    Draw,
+    Loss,
+}
+
+/// Exact value of a position, see [`AI::solve`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Solution {
+    pub outcome: Outcome,
+    /// Number of moves left to play, of both players and including the
+    /// winning one, when the game is won with best play on both sides.
+    pub moves_to_win: Option<u8>,
+    /// A move reaching that result, `None` if the board is full.
+    pub best_move: Option<ColIdx>,
+    pub score: Score,
+}
+
+impl Solution {
+    fn new(b: &Board, best_move: Option<ColIdx>, score: Score) -> Self {
+        let outcome = score.outcome();
+        // The winner plays its last token at turn 2 * tokens - 1 if it moves
+        // first, 2 * tokens otherwise.
+        let winner_to_move = outcome == Outcome::Win;
+        let moves_to_win = score.winner_tokens().map(|tokens| {
+            let first_player = (b.to_play() == NonEmptySqrState::Red) == winner_to_move;
+            let turn = if first_player {
+                2 * tokens - 1
+            } else {
+                2 * tokens
+            };
+            turn - b.occupancy()
+        });
+        Self {
+            outcome,
+            moves_to_win,
+            best_move,
+            score,
+        }
+    }
+}
+
+/// The moves of `analysis` other than the best one that are not known to
+/// lose, unless the best one loses too, with their score. None when there is
+/// an immediate win, not to be missed. An upper bound does not tell that a
+/// move does not lose: only exact scores are trusted, see
+/// [`AI::resolve_bounds`].
+fn suboptimal_candidates(analysis: &Analysis) -> Vec<(ColIdx, Score)> {
+    if analysis.columns.contains(&ColumnAnalysis::ImmediateWin) {
+        return Vec::new();
+    }
+    let best_loses = analysis.score.is_proven() && analysis.score.get() < 0;
+    ALL_COL_IDXS
+        .iter()
+        .zip(&analysis.columns)
+        .filter(|&(&coli, _)| Some(coli) != analysis.best_move)
+        .filter_map(|(&coli, column)| match *column {
+            ColumnAnalysis::Searched {
+                score,
+                bound: Bound::Exact,
+            } if best_loses || !(score.is_proven() && score.get() < 0) => Some((coli, score)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// What [`AI::analyze`] found about playing in one column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnAnalysis {
+    /// The column is full, the move is illegal.
+    Full,
+    /// The move wins right away.
+    ImmediateWin,
+    /// The move lets the opponent win right away.
+    ImmediateLoss,
+    /// Score of the move for the player about to move, as far as the search
+    /// goes: the true score is `score` itself, or on the side of it given by
+    /// `bound`.
+    Searched { score: Score, bound: Bound },
+    /// The search was stopped before it could tell anything about the move.
+    Unsearched,
+}
+
+/// Every column of a position, analysed by [`AI::analyze`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Analysis {
+    pub columns: [ColumnAnalysis; NCOL as usize],
+    pub best_move: Option<ColIdx>,
+    pub score: Score,
+    /// Depth of the search of the best move: scores of 0 are draws when it
+    /// reaches the end of the game.
+    pub depth: u8,
+}
+
+/// How the stored score relates to the true value of the position, as in
+/// a classic alpha/beta transposition table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    /// The search ended inside the window: the score is the value.
+    Exact,
+    /// The search failed high: the value is at least the score.
+    Lower,
+    /// The search failed low: the value is at most the score.
+    Upper,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CacheEntry {
+    col: ColIdx,
+    score: Score,
+    bound: Bound,
+    /// Remaining search depth that produced this entry.
+    depth: u8,
+}
+
+impl CacheEntry {
+    /// Whether the entry holds the exact value of `b`, which depends neither
+    /// on the evaluator nor on the depth of the search: a win or a loss, or
+    /// any score searched down to the end of the game, as a draw is.
+    fn is_solved(&self, b: &Board) -> bool {
+        self.bound == Bound::Exact
+            && (self.score.is_proven() || self.depth >= GRID_SIZE - b.occupancy())
+    }
+}
+
+const CACHE_SHARDS: usize = 64;
+
+const CACHE_MAGIC: &[u8; 8] = b"F4RCACHE";
+const CACHE_VERSION: u8 = 2;
+/// Key, score, column, bound and depth.
+const CACHE_ENTRY_SIZE: usize = 13;
+
+/// 64-bit FNV-1a hash of `bytes`, continuing from `hash`.
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &byte in bytes {
+        hash = (hash ^ byte as u64).wrapping_mul(0x100_0000_01B3);
     }
-    (res_col, res_score)
+    hash
 }
-#[derive(Debug,Clone, Copy)]
-enum CacheEntry {
-    Exact(ColIdx,Score),
-    Unknown{turn: u8},
+
+const FNV_OFFSET: u64 = 0xCBF2_9CE4_8422_2325;
+
+/// Games an entry of the cache is kept for after the last one it was used
+/// in, when its score is proven. Other entries only last until the end of
+/// the next game, since they depend on the search horizon of the positions
+/// met in their own game.
+const PROVEN_MAX_AGE: u32 = 8;
+
+#[derive(Debug, Clone, Copy)]
+struct CacheSlot {
+    entry: CacheEntry,
+    /// Game the entry was last stored or found in, see [`Cache::game`].
+    game: u32,
 }
 
+/// One shard of the cache, with the counters of [`Cache::probe`].
+#[derive(Default)]
+struct Shard {
+    slots: AHashMap<Board, CacheSlot>,
+    probes: u64,
+    hits: u64,
+    carried_hits: u64,
+}
+
+/// How useful the cache of an [`AI`] has been, over every game it served.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Games served, the current one included, see [`AI::new_game`].
+    pub games: u32,
+    pub entries: usize,
+    /// Positions looked for by the search, and how many of them were found.
+    pub probes: u64,
+    pub hits: u64,
+    /// Hits on entries last used in an earlier game.
+    pub carried_hits: u64,
+    /// Entries forgotten at the start of a game.
+    pub aged_out: u64,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        self.hits as f64 / self.probes.max(1) as f64
+    }
+}
+
+impl std::fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} games, {} entries, {:.1}% of {} probes hit ({} from earlier games), {} entries aged out",
+            self.games,
+            self.entries,
+            100.0 * self.hit_rate(),
+            self.probes,
+            self.carried_hits,
+            self.aged_out
+        )
+    }
+}
+
+/// Transposition table, split into independently locked shards so that
+/// parallel searches rarely wait for each other. A single thread locks a
+/// shard without contention on every probe and store, which is cheap next
+/// to the rest of a node: single-threaded searches were within 5% of the
+/// same searches over an unlocked table.
 struct Cache {
-    result: ahash::AHashMap<Board, CacheEntry>,
-    lower_bounds: ahash::AHashMap<Board, Score>,
+    shards: Vec<Mutex<Shard>>,
+    /// Number of the current game, from 0.
+    game: u32,
+    aged_out: u64,
 }
 
 impl Cache {
     fn new() -> Self {
         Self {
-            result: AHashMap::with_capacity(100_000),
-            lower_bounds: AHashMap::with_capacity(100_000),
+            shards: (0..CACHE_SHARDS)
+                .map(|_| {
+                    Mutex::new(Shard {
+                        slots: AHashMap::with_capacity(100_000 / CACHE_SHARDS),
+                        ..Shard::default()
+                    })
+                })
+                .collect(),
+            game: 0,
+            aged_out: 0,
         }
     }
+
+    fn shard(&self, b: &Board) -> &Mutex<Shard> {
+        // Fibonacci hashing: the top bits of the product depend on all the
+        // bits of the key.
+        let hash = b.key().wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        &self.shards[(hash >> (64 - CACHE_SHARDS.trailing_zeros())) as usize]
+    }
+
+    fn get(&self, b: &Board) -> Option<CacheEntry> {
+        let shard = self.shard(b).lock().unwrap();
+        shard.slots.get(b).map(|slot| slot.entry)
+    }
+
+    /// Like [`Cache::get`], for the search: counts in the statistics, and
+    /// marks the entry as used in the current game.
+    fn probe(&self, b: &Board) -> Option<CacheEntry> {
+        let mut shard = self.shard(b).lock().unwrap();
+        let shard = &mut *shard;
+        shard.probes += 1;
+        let slot = shard.slots.get_mut(b)?;
+        shard.hits += 1;
+        if slot.game != self.game {
+            shard.carried_hits += 1;
+            slot.game = self.game;
+        }
+        Some(slot.entry)
+    }
+
+    /// Stores `entry`, unless a deeper search of the same position is
+    /// already known.
+    fn store(&self, b: &Board, entry: CacheEntry) {
+        let game = self.game;
+        self.shard(b)
+            .lock()
+            .unwrap()
+            .slots
+            .entry(b.clone())
+            .and_modify(|old| {
+                if entry.depth >= old.entry.depth {
+                    old.entry = entry
+                }
+                old.game = game;
+            })
+            .or_insert(CacheSlot { entry, game });
+    }
+
+    /// Starts a new game, forgetting the entries too old to be worth
+    /// keeping, see [`PROVEN_MAX_AGE`].
+    fn new_game(&mut self) {
+        self.game += 1;
+        let game = self.game;
+        for shard in &mut self.shards {
+            let slots = &mut shard.get_mut().unwrap().slots;
+            let before = slots.len();
+            slots.retain(|_, slot| {
+                let age = game - slot.game;
+                age <= 1 || (slot.entry.score.is_proven() && age <= PROVEN_MAX_AGE)
+            });
+            self.aged_out += (before - slots.len()) as u64;
+        }
+    }
+
+    fn stats(&self) -> CacheStats {
+        let mut stats = CacheStats {
+            games: self.game + 1,
+            aged_out: self.aged_out,
+            ..CacheStats::default()
+        };
+        for shard in &self.shards {
+            let shard = shard.lock().unwrap();
+            stats.entries += shard.slots.len();
+            stats.probes += shard.probes;
+            stats.hits += shard.hits;
+            stats.carried_hits += shard.carried_hits;
+        }
+        stats
+    }
+
+    /// Writes the entries that are solved, sorted by [`Board::key`] so that
+    /// the same cache always gives the same file, followed by a checksum.
+    /// The others depend on the evaluator and the horizon of the search
+    /// that stored them, and would mislead another AI.
+    fn write_to(&self, mut w: impl Write) -> io::Result<()> {
+        let mut entries: Vec<(u64, CacheEntry)> = self
+            .shards
+            .iter()
+            .flat_map(|shard| {
+                let shard = shard.lock().unwrap();
+                shard
+                    .slots
+                    .iter()
+                    .filter(|(b, slot)| slot.entry.is_solved(b))
+                    .map(|(b, slot)| (b.key(), slot.entry))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        entries.sort_unstable_by_key(|&(key, _)| key);
+        w.write_all(CACHE_MAGIC)?;
+        w.write_all(&[CACHE_VERSION])?;
+        w.write_all(&(entries.len() as u64).to_le_bytes())?;
+        let mut checksum = FNV_OFFSET;
+        for (key, entry) in entries {
+            let mut buf = [0; CACHE_ENTRY_SIZE];
+            buf[..8].copy_from_slice(&key.to_le_bytes());
+            buf[8..10].copy_from_slice(&entry.score.get().to_le_bytes());
+            buf[10] = entry.col.get();
+            buf[11] = entry.bound as u8;
+            buf[12] = entry.depth;
+            checksum = fnv1a(checksum, &buf);
+            w.write_all(&buf)?;
+        }
+        w.write_all(&checksum.to_le_bytes())?;
+        w.flush()
+    }
+
+    /// Reads entries written by [`Cache::write_to`] into the cache. Nothing
+    /// is stored unless the whole file is valid.
+    fn read_from(&self, mut r: impl Read) -> io::Result<usize> {
+        let mut header = [0; 17];
+        r.read_exact(&mut header)?;
+        if &header[..8] != CACHE_MAGIC {
+            return Err(invalid_data("not a cache file"));
+        }
+        if header[8] != CACHE_VERSION {
+            return Err(invalid_data("unsupported cache file version"));
+        }
+        let len = u64::from_le_bytes(header[9..].try_into().unwrap());
+        let mut entries = Vec::new();
+        let mut checksum = FNV_OFFSET;
+        let mut buf = [0; CACHE_ENTRY_SIZE];
+        for _ in 0..len {
+            r.read_exact(&mut buf)?;
+            checksum = fnv1a(checksum, &buf);
+            let key = u64::from_le_bytes(buf[..8].try_into().unwrap());
+            let b = Board::from_key(key).ok_or_else(|| invalid_data("invalid position"))?;
+            let score = Score::from_raw(i16::from_le_bytes(buf[8..10].try_into().unwrap()))
+                .ok_or_else(|| invalid_data("score out of range"))?;
+            let col = ColIdx::new(buf[10]).ok_or_else(|| invalid_data("invalid column"))?;
+            let bound = match buf[11] {
+                0 => Bound::Exact,
+                1 => Bound::Lower,
+                2 => Bound::Upper,
+                _ => return Err(invalid_data("invalid bound")),
+            };
+            let depth = buf[12];
+            let entry = CacheEntry {
+                col,
+                score,
+                bound,
+                depth,
+            };
+            if !entry.is_solved(&b) {
+                return Err(invalid_data("unsolved cache entry"));
+            }
+            entries.push((b, entry));
+        }
+        let mut stored = [0; 8];
+        r.read_exact(&mut stored)?;
+        if u64::from_le_bytes(stored) != checksum {
+            return Err(invalid_data("cache file checksum mismatch"));
+        }
+        for (b, entry) in &entries {
+            self.store(b, *entry);
+        }
+        Ok(entries.len())
+    }
+}
+
+/// Deepens the search of `b` one ply at a time from `first_depth`: the
+/// shallower searches are cheap and fill the cache with the best moves to
+/// try first in the deeper ones. Also returns the depth of the last complete
+/// iteration, 0 if none.
+fn iterative_deepening(
+    b: &Board,
+    first_depth: u8,
+    max_depth: u8,
+    solver: Solver,
+    search: &mut Search,
+) -> (Option<ColIdx>, Score, u8) {
+    let turn = b.occupancy() + 1;
+    let color = b.to_play();
+    let mut res = (None, Score::new(0));
+    let mut reached = 0;
+    let exact = max_depth == GRID_SIZE - b.occupancy();
+    // The null-window calls of an exact search need no shallower one to
+    // order their moves, and are much quicker without.
+    let first_depth = if solver == Solver::NullWindow && exact {
+        max_depth
+    } else {
+        first_depth
+    };
+    for depth in first_depth..=max_depth {
+        let iteration = match solver {
+            // Shallower searches end on evaluations, not just on the
+            // possible scores.
+            Solver::NullWindow if exact => null_window_search(b, turn, color, search),
+            Solver::FullWindow | Solver::NullWindow => {
+                negamax(b, depth, turn, color, search, Score::MIN, Score::MAX)
+            }
+            Solver::Weak => negamax(b, depth, turn, color, search, -Score::new(1), Score::new(1)),
+        };
+        if search.stopped() {
+            // Keep the result of the last complete iteration.
+            break;
+        }
+        res = iteration;
+        reached = depth;
+        if res.1.is_proven() {
+            break;
+        }
+    }
+    (res.0, res.1, reached)
 }
 
-pub struct AI(Cache);
+/// What the searches of an [`AI`] went through, all threads included.
+#[derive(Debug, Default, Clone, Copy)]
+struct SearchStats {
+    nodes: u64,
+    tt_hits: u64,
+    tt_cutoffs: u64,
+    /// Depth of the last complete iteration of the main search.
+    depth: u8,
+}
+
+impl SearchStats {
+    /// Adds the counters of `search`, leaving the depth alone.
+    fn add(&mut self, search: &Search) {
+        self.nodes += search.nodes;
+        self.tt_hits += search.tt_hits;
+        self.tt_cutoffs += search.tt_cutoffs;
+    }
+}
+
+pub struct AI {
+    cache: Cache,
+    history: History,
+    eval: Box<dyn Evaluate>,
+    ordering: MoveOrdering,
+    solver: Solver,
+    threads: usize,
+    book: Option<Book>,
+    tablebase: Option<Tablebase>,
+    max_depth: u8,
+    /// Probability of playing a good move other than the best one.
+    suboptimal_probability: f64,
+    rng: Rng,
+    /// Position set through [`Engine::set_position`].
+    position: Board,
+    cancel: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+    /// Since the start of the current move, see [`AI::think`].
+    stats: SearchStats,
+}
+
+impl Default for AI {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl AI {
     pub fn new() -> Self {
-        AI(Cache::new())
-    }
-
-    pub fn make_a_move(&mut self, b: &Board) -> ColIdx {
-        let cache = &mut self.0;
-        // negamax(
-        //     b,
-        //     20,
-        //     b.occupancy() + 1,
-        //     AI_COLOR,
-        //     cache,
-        //     Score::MIN,
-        //     Score::MAX,
-        // );
-        // negamax(
-        //     b,
-        //     30,
-        //     b.occupancy() + 1,
-        //     AI_COLOR,
-        //     cache,
-        //     Score::MIN,
-        //     Score::MAX,
-        // );
-        let (res, reason) = negamax(
-            b,
-            MAX_DEPTH,
-            b.occupancy() + 1,
-            AI_COLOR,
-            cache,
-            Score::MIN,
-            Score::MAX,
-        );
-        println!("Cache capacities: {}, {}", cache.result.capacity(), cache.lower_bounds.capacity());
-        println!("Move chosen because: {}", reason,);
-        res.unwrap()
+        Self::with_evaluator(Box::new(Heuristic::default()))
+    }
+
+    /// An AI that scores the positions at its search horizon with `eval`.
+    pub fn with_evaluator(eval: Box<dyn Evaluate>) -> Self {
+        AI {
+            cache: Cache::new(),
+            history: History::new(),
+            eval,
+            ordering: MoveOrdering::Dynamic,
+            solver: Solver::NullWindow,
+            threads: 1,
+            book: None,
+            tablebase: None,
+            max_depth: MAX_DEPTH,
+            suboptimal_probability: 0.0,
+            rng: Rng::new(0),
+            position: Board::new(),
+            cancel: Arc::new(AtomicBool::new(false)),
+            deadline: None,
+            stats: SearchStats::default(),
+        }
+    }
+
+    /// Plays at the strength of `difficulty`. `seed` determines the noise
+    /// added to evaluations and which imperfect moves are played, so that the
+    /// same seed replays the same game against the same moves.
+    pub fn with_difficulty(mut self, difficulty: Difficulty, seed: u64) -> Self {
+        self.max_depth = difficulty.max_depth();
+        self.suboptimal_probability = difficulty.suboptimal_probability();
+        self.rng = Rng::new(seed);
+        if difficulty.eval_noise() > 0 {
+            self.eval = Box::new(Noisy {
+                inner: self.eval,
+                amplitude: difficulty.eval_noise(),
+                seed,
+            });
+        }
+        self
+    }
+
+    pub fn with_move_ordering(mut self, ordering: MoveOrdering) -> Self {
+        self.ordering = ordering;
+        self
+    }
+
+    pub fn with_solver(mut self, solver: Solver) -> Self {
+        self.solver = solver;
+        self
+    }
+
+    /// Plays the moves of `book` in the positions it knows, without
+    /// searching, unless the difficulty makes it deviate.
+    pub fn with_book(mut self, book: Book) -> Self {
+        self.book = Some(book);
+        self
+    }
+
+    /// Reads the values of the endgames in `tablebase` instead of searching
+    /// them.
+    pub fn with_tablebase(mut self, tablebase: Tablebase) -> Self {
+        self.tablebase = Some(tablebase);
+        self
+    }
+
+    /// Searches with `threads` threads sharing the cache (at least one).
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = max(threads, 1);
+        self
+    }
+
+    /// Saves the positions the searches solved so far, to be reused by later
+    /// runs with [`AI::load_cache`], whatever their evaluator.
+    pub fn save_cache(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.cache.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    /// Adds the entries saved by [`AI::save_cache`] to the cache, keeping
+    /// the deeper one where both know a position. Returns the number of
+    /// entries read.
+    pub fn load_cache(&mut self, path: impl AsRef<Path>) -> io::Result<usize> {
+        self.cache.read_from(BufReader::new(File::open(path)?))
+    }
+
+    /// Prepares for a new game from the empty board: forgets the move
+    /// ordering statistics, and the entries of the cache that are unlikely
+    /// to be useful again, so that an AI serving game after game does not
+    /// keep growing.
+    pub fn new_game(&mut self) {
+        self.position = Board::new();
+        self.history = History::new();
+        self.cache.new_game();
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// Lazy SMP: `threads - 1` helpers search the same position as the main
+    /// thread, each in a slightly different order, and share what they find
+    /// through the cache. Only the result of the main thread is used; the
+    /// helpers are stopped as soon as it is known.
+    fn parallel_search(
+        &mut self,
+        b: &Board,
+        max_depth: u8,
+        solver: Solver,
+    ) -> (Option<ColIdx>, Score) {
+        let (cache, eval, history) = (&self.cache, &*self.eval, &mut self.history);
+        let tablebase = self.tablebase.as_ref();
+        let (ordering, threads, deadline) = (self.ordering, self.threads, self.deadline);
+        let cancel = &*self.cancel;
+        let stats = &mut self.stats;
+        let stop = AtomicBool::new(false);
+        let (col, score, depth) = thread::scope(|scope| {
+            let helpers: Vec<_> = (1..threads)
+                .map(|helper| {
+                    let stop = &stop;
+                    scope.spawn(move || {
+                        let mut history = History::new();
+                        let mut search = Search {
+                            cache,
+                            eval,
+                            history: &mut history,
+                            ordering,
+                            nodes: 0,
+                            tt_hits: 0,
+                            tt_cutoffs: 0,
+                            stop,
+                            cancel,
+                            deadline,
+                            rotation: helper,
+                            tablebase,
+                        };
+                        let first_depth = 1 + (helper % 2) as u8;
+                        iterative_deepening(b, first_depth, max_depth, solver, &mut search);
+                        (search.nodes, search.tt_hits, search.tt_cutoffs)
+                    })
+                })
+                .collect();
+            let mut search = Search {
+                cache,
+                eval,
+                history,
+                ordering,
+                nodes: 0,
+                tt_hits: 0,
+                tt_cutoffs: 0,
+                stop: &stop,
+                cancel,
+                deadline,
+                rotation: 0,
+                tablebase,
+            };
+            let res = iterative_deepening(b, 1, max_depth, solver, &mut search);
+            stop.store(true, Ordering::Relaxed);
+            stats.add(&search);
+            for helper in helpers {
+                let (nodes, tt_hits, tt_cutoffs) = helper.join().unwrap();
+                stats.nodes += nodes;
+                stats.tt_hits += tt_hits;
+                stats.tt_cutoffs += tt_cutoffs;
+            }
+            res
+        });
+        stats.depth = depth;
+        (col, score)
+    }
+
+    /// Exact value of `b` for the player about to move, with a best move.
+    /// Unlike [`AI::make_a_move`], the search is not limited in depth: it
+    /// goes on until the game is decided, which may take a long time early
+    /// in the game.
+    pub fn solve(&mut self, b: &Board) -> Result<Solution, MoveError> {
+        check_playable(b)?;
+        let (col, score) = self.parallel_search(b, GRID_SIZE - b.occupancy(), self.solver);
+        Ok(Solution::new(b, col, score))
+    }
+
+    /// Who wins `b` with best play, like [`AI::solve`] but without telling
+    /// how fast, which makes the search quicker.
+    pub fn weak_solve(&mut self, b: &Board) -> Result<Outcome, MoveError> {
+        check_playable(b)?;
+        let (_, score) = self.parallel_search(b, GRID_SIZE - b.occupancy(), Solver::Weak);
+        Ok(score.outcome())
+    }
+
+    /// The moves expected after `first` is played in `b`, as far as the
+    /// cache or the book know them exactly, and no further than `horizon`
+    /// tokens: past the depth of the search, the cache only holds what
+    /// earlier searches guessed.
+    fn principal_variation(&self, b: &Board, first: ColIdx, horizon: u8) -> Vec<ColIdx> {
+        let mut pv = vec![first];
+        let mut b = b.clone();
+        let mut col = first;
+        while pv.len() < horizon as usize {
+            match b.add_and_check(col, b.to_play()) {
+                Ok(false) => {}
+                Ok(true) | Err(_) => return pv,
+            }
+            let remaining = horizon - pv.len() as u8;
+            // `negamax` does not cache the positions it wins right away.
+            let next = winning_move(&b)
+                .or_else(|| {
+                    let entry = self.cache.get(&b)?;
+                    // A bound only tells that the move was good enough for a
+                    // cutoff, and a shallower entry is left from an earlier
+                    // iteration.
+                    let exact = entry.bound == Bound::Exact && entry.depth >= remaining;
+                    exact.then_some(entry.col)
+                })
+                .or_else(|| {
+                    let book = self.book.as_ref()?;
+                    book.lookup(&b).map(|(col, _)| col)
+                })
+                .or_else(|| {
+                    let tablebase = self.tablebase.as_ref()?;
+                    tablebase.lookup(&b).map(|(col, _)| col)
+                });
+            match next {
+                Some(next) if b.occupancy() < GRID_SIZE => {
+                    pv.push(next);
+                    col = next;
+                }
+                _ => return pv,
+            }
+        }
+        pv
+    }
+
+    /// Looks at every column of `b`, with the same search and cache as
+    /// [`AI::make_a_move`]. The best move gets an exact score. The others
+    /// are only searched far enough to tell that they are not better, and
+    /// get an upper bound unless they are just as good. When the search is
+    /// stopped, the columns it had no time for are left unsearched.
+    pub fn analyze(&mut self, b: &Board) -> Result<Analysis, MoveError> {
+        check_playable(b)?;
+        let (best_move, score) = self.parallel_search(b, self.max_depth, self.solver);
+        let turn = b.occupancy() + 1;
+        let color = b.to_play();
+        let stop = AtomicBool::new(false);
+        let mut search = Search {
+            cache: &self.cache,
+            eval: &*self.eval,
+            history: &mut self.history,
+            ordering: self.ordering,
+            nodes: 0,
+            tt_hits: 0,
+            tt_cutoffs: 0,
+            stop: &stop,
+            cancel: &self.cancel,
+            deadline: self.deadline,
+            rotation: 0,
+            tablebase: self.tablebase.as_ref(),
+        };
+        let mut columns = [ColumnAnalysis::Full; NCOL as usize];
+        for (&coli, analysis) in ALL_COL_IDXS.iter().zip(&mut columns) {
+            let mut child = b.clone();
+            *analysis = match child.add_and_check(coli, color) {
+                Err(_) => ColumnAnalysis::Full,
+                Ok(true) => ColumnAnalysis::ImmediateWin,
+                Ok(false) if winning_move(&child).is_some() => ColumnAnalysis::ImmediateLoss,
+                Ok(false) if Some(coli) == best_move => ColumnAnalysis::Searched {
+                    score,
+                    bound: Bound::Exact,
+                },
+                Ok(false) => {
+                    // Null-window test of whether the move is as good as the
+                    // best one, from the point of view of the opponent.
+                    let (_, child_score) = negamax(
+                        &child,
+                        self.max_depth - 1,
+                        turn + 1,
+                        color.other(),
+                        &mut search,
+                        -score,
+                        (-score).next(),
+                    );
+                    if search.stopped() {
+                        ColumnAnalysis::Unsearched
+                    } else if child_score <= -score {
+                        ColumnAnalysis::Searched {
+                            score,
+                            bound: Bound::Exact,
+                        }
+                    } else {
+                        ColumnAnalysis::Searched {
+                            score: -child_score,
+                            bound: Bound::Upper,
+                        }
+                    }
+                }
+            };
+        }
+        self.stats.add(&search);
+        Ok(Analysis {
+            columns,
+            best_move,
+            score,
+            depth: self.stats.depth,
+        })
+    }
+
+    /// Searches again with a full window the columns of `analysis` that
+    /// only have an upper bound, so that every searched column gets an exact
+    /// score, as far as the search goes. The columns the search has no time
+    /// for become unsearched.
+    fn resolve_bounds(&mut self, b: &Board, analysis: &mut Analysis) {
+        let turn = b.occupancy() + 1;
+        let color = b.to_play();
+        let stop = AtomicBool::new(false);
+        let mut search = Search {
+            cache: &self.cache,
+            eval: &*self.eval,
+            history: &mut self.history,
+            ordering: self.ordering,
+            nodes: 0,
+            tt_hits: 0,
+            tt_cutoffs: 0,
+            stop: &stop,
+            cancel: &self.cancel,
+            deadline: self.deadline,
+            rotation: 0,
+            tablebase: self.tablebase.as_ref(),
+        };
+        for (&coli, column) in ALL_COL_IDXS.iter().zip(&mut analysis.columns) {
+            if let ColumnAnalysis::Searched {
+                bound: Bound::Upper,
+                ..
+            } = *column
+            {
+                let mut child = b.clone();
+                child.add_and_check(coli, color).unwrap();
+                let (_, child_score) = negamax(
+                    &child,
+                    self.max_depth - 1,
+                    turn + 1,
+                    color.other(),
+                    &mut search,
+                    Score::MIN,
+                    Score::MAX,
+                );
+                *column = if search.stopped() {
+                    ColumnAnalysis::Unsearched
+                } else {
+                    ColumnAnalysis::Searched {
+                        score: -child_score,
+                        bound: Bound::Exact,
+                    }
+                };
+            }
+        }
+        self.stats.add(&search);
+    }
+
+    pub fn make_a_move(&mut self, b: &Board) -> Result<ColIdx, MoveError> {
+        Ok(self.think(b)?.best_move)
+    }
+
+    /// Why `col` is worth playing in `b`, from the [analysis](AI::analyze)
+    /// of `b`, which mostly finds its answers in the cache right after `b`
+    /// was searched. Once the game is over, only the board can tell.
+    pub fn explain(&mut self, b: &Board, col: ColIdx) -> Explanation {
+        match self.analyze(b) {
+            Ok(analysis) => Explanation::new(b, col, &analysis),
+            Err(_) => Explanation::tactical(b, col),
+        }
+    }
+
+    /// Uses the time the opponent takes to choose their move in `b` to
+    /// search the positions they may leave, the most likely one first, so
+    /// that the cache already knows them when [`AI::make_a_move`] is called.
+    /// Returns once every reply is searched, or when stopped through
+    /// [`Engine::stop_handle`].
+    pub fn ponder(&mut self, b: &Board) {
+        if check_playable(b).is_err() {
+            return;
+        }
+        let prediction_depth = min(self.max_depth, PONDER_PREDICTION_DEPTH);
+        let (predicted, _) = self.parallel_search(b, prediction_depth, self.solver);
+        let others = COLS_ORDER.iter().copied().filter(|&c| Some(c) != predicted);
+        for reply in predicted.into_iter().chain(others) {
+            if self.cancel.load(Ordering::Relaxed) {
+                return;
+            }
+            let mut child = b.clone();
+            match child.add_and_check(reply, b.to_play()) {
+                Ok(false) if child.occupancy() < GRID_SIZE => {}
+                _ => continue,
+            }
+            if self
+                .book
+                .as_ref()
+                .is_some_and(|book| book.lookup(&child).is_some())
+            {
+                continue;
+            }
+            self.parallel_search(&child, self.max_depth, self.solver);
+        }
+    }
+
+    /// Chooses a move for `b` like [`AI::make_a_move`], and tells how.
+    pub fn think(&mut self, b: &Board) -> Result<SearchInfo, MoveError> {
+        check_playable(b)?;
+        let start = Instant::now();
+        self.stats = SearchStats::default();
+        // Drawn on every move, so that a game only depends on the seed and
+        // the moves of the opponent, and before the book is looked at, so
+        // that a weakened AI deviates from it too.
+        let deviate = self.rng.next_f64() < self.suboptimal_probability;
+        if deviate {
+            let mut analysis = self.analyze(b)?;
+            self.resolve_bounds(b, &mut analysis);
+            let candidates = suboptimal_candidates(&analysis);
+            if !candidates.is_empty() {
+                let (col, score) = candidates[self.rng.below(candidates.len())];
+                return Ok(self.search_info(b, col, MoveSource::Deviation, score, start));
+            }
+        }
+        if let Some((col, score)) = self.book.as_ref().and_then(|book| book.lookup(b)) {
+            return Ok(self.search_info(b, col, MoveSource::Book, score, start));
+        }
+        let (res, score) = self.parallel_search(b, self.max_depth, self.solver);
+        // Only missing if stopped before the shallowest search was over.
+        let res = res.unwrap_or_else(|| self.fallback_move(b));
+        Ok(self.search_info(b, res, MoveSource::Search, score, start))
+    }
+
+    /// A move for `b` when there was no time to search it: the best move
+    /// known from earlier searches, or else one not losing right away.
+    fn fallback_move(&self, b: &Board) -> ColIdx {
+        let loses_at_once = |coli| {
+            let mut child = b.clone();
+            match child.add_and_check(coli, b.to_play()) {
+                Ok(won) => !won && winning_move(&child).is_some(),
+                Err(_) => true,
+            }
+        };
+        winning_move(b)
+            .or_else(|| self.cache.get(b).map(|entry| entry.col))
+            .or_else(|| COLS_ORDER.iter().copied().find(|&c| !loses_at_once(c)))
+            .or_else(|| COLS_ORDER.iter().copied().find(|&c| !b.col_is_full(c)))
+            .unwrap()
+    }
+
+    fn search_info(
+        &self,
+        b: &Board,
+        best_move: ColIdx,
+        source: MoveSource,
+        score: Score,
+        start: Instant,
+    ) -> SearchInfo {
+        // The book is exact up to the end of the game.
+        let horizon = match source {
+            MoveSource::Book => GRID_SIZE - b.occupancy(),
+            _ => self.stats.depth,
+        };
+        SearchInfo {
+            best_move,
+            source,
+            score: Some(score),
+            win_rate: None,
+            depth: self.stats.depth,
+            nodes: self.stats.nodes,
+            tt_hits: self.stats.tt_hits,
+            tt_cutoffs: self.stats.tt_cutoffs,
+            elapsed: start.elapsed(),
+            pv: self.principal_variation(b, best_move, horizon),
+        }
+    }
+}
+
+impl Engine for AI {
+    fn name(&self) -> &'static str {
+        "negamax"
+    }
+
+    fn new_game(&mut self) {
+        self.new_game();
+    }
+
+    fn set_position(&mut self, b: &Board) {
+        self.position = b.clone();
+    }
+
+    fn go(&mut self, limits: &Limits) -> Result<SearchInfo, MoveError> {
+        self.limited(limits, |ai, b| ai.think(b))
+    }
+
+    fn stop_handle(&self) -> StopHandle {
+        StopHandle::new(self.cancel.clone())
+    }
+
+    fn ponder(&mut self) {
+        let b = self.position.clone();
+        self.ponder(&b);
+    }
+
+    fn analyze(&mut self, limits: &Limits) -> Result<[Assessment; NCOL as usize], MoveError> {
+        let analysis = self.limited(limits, |ai, b| ai.analyze(b))?;
+        Ok(analysis.columns.map(Assessment::Searched))
+    }
+
+    fn explain(&mut self, col: ColIdx, limits: &Limits) -> Explanation {
+        self.within(limits, |ai, b| ai.explain(b, col))
+    }
+}
+
+impl AI {
+    /// Runs `f` on the current position within `limits`, then restores the
+    /// settings they replace. A stop requested before is forgotten.
+    fn limited<T>(&mut self, limits: &Limits, f: impl FnOnce(&mut Self, &Board) -> T) -> T {
+        self.cancel.store(false, Ordering::Relaxed);
+        self.within(limits, f)
+    }
+
+    /// Like [`AI::limited`], but still stopped if a stop was requested since
+    /// the last search started.
+    fn within<T>(&mut self, limits: &Limits, f: impl FnOnce(&mut Self, &Board) -> T) -> T {
+        let max_depth = self.max_depth;
+        self.max_depth = limits.depth.map_or(max_depth, |depth| max(depth, 1));
+        self.deadline = limits.time.map(|time| Instant::now() + time);
+        let b = self.position.clone();
+        let res = f(self, &b);
+        self.max_depth = max_depth;
+        self.deadline = None;
+        res
     }
 }
 
@@ -233,3 +1475,811 @@ impl AI {
 //         .or_else(|| loosing_positions.first())
 //         .unwrap()
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::Neutral;
+    use crate::explain::Reason;
+    use crate::tablebase::{Tablebase, MAX_POSITIONS};
+    use std::time::Duration;
+
+    /// Plain alpha/beta without any transposition table.
+    fn reference(
+        b: &Board,
+        depth: u8,
+        turn: u8,
+        color: NonEmptySqrState,
+        eval: &dyn Evaluate,
+    ) -> Score {
+        fn go(
+            b: &Board,
+            depth: u8,
+            turn: u8,
+            color: NonEmptySqrState,
+            eval: &dyn Evaluate,
+            mut alpha: Score,
+            beta: Score,
+        ) -> Score {
+            if turn > GRID_SIZE {
+                return Score::new(0);
+            }
+            if depth == 0 {
+                return Score::heuristic(eval.evaluate(b, color));
+            }
+            let mut children = Vec::new();
+            for &col in &COLS_ORDER {
+                let mut b1 = b.clone();
+                match b1.add_and_check(col, color) {
+                    Ok(true) => return Score::win(22 - turn.div_ceil(2) as i8),
+                    Ok(false) => children.push(b1),
+                    Err(_) => continue,
+                }
+            }
+            let mut best = Score::MIN;
+            for child in children {
+                let score = -go(
+                    &child,
+                    depth - 1,
+                    turn + 1,
+                    color.other(),
+                    eval,
+                    -beta,
+                    -alpha,
+                );
+                best = max(best, score);
+                alpha = max(alpha, score);
+                if alpha >= beta {
+                    break;
+                }
+            }
+            best
+        }
+        go(b, depth, turn, color, eval, Score::MIN, Score::MAX)
+    }
+
+    const POSITIONS: [&str; 6] = [
+        "",
+        "4453",
+        "44444433",
+        "2252576253462244111563365343671351441",
+        "7422341735647741166133573473242566",
+        "23163416124767223154467471272416755633",
+    ];
+
+    const EVALUATORS: [&dyn Evaluate; 2] = [
+        &Neutral,
+        &Heuristic {
+            threat: 8,
+            parity_threat: 12,
+            immediate_threat: 40,
+            center: 1,
+        },
+    ];
+
+    /// Never set: searches of the tests only stop at their deadline.
+    static NEVER: AtomicBool = AtomicBool::new(false);
+
+    impl<'a> Search<'a> {
+        fn for_test(cache: &'a Cache, eval: &'a dyn Evaluate, history: &'a mut History) -> Self {
+            Search {
+                cache,
+                eval,
+                history,
+                ordering: MoveOrdering::Dynamic,
+                nodes: 0,
+                tt_hits: 0,
+                tt_cutoffs: 0,
+                stop: &NEVER,
+                cancel: &NEVER,
+                deadline: None,
+                rotation: 0,
+                tablebase: None,
+            }
+        }
+    }
+
+    fn search(
+        b: &Board,
+        depth: u8,
+        cache: &Cache,
+        eval: &dyn Evaluate,
+        ordering: MoveOrdering,
+    ) -> (Score, u64) {
+        let mut history = History::new();
+        let mut search = Search {
+            ordering,
+            ..Search::for_test(cache, eval, &mut history)
+        };
+        let (_, score) = negamax(
+            b,
+            depth,
+            b.occupancy() + 1,
+            b.to_play(),
+            &mut search,
+            Score::MIN,
+            Score::MAX,
+        );
+        (score, search.nodes)
+    }
+
+    #[test]
+    fn cached_matches_uncached() {
+        for moves in &POSITIONS {
+            for &eval in &EVALUATORS {
+                let b = Board::from_moves(moves).unwrap();
+                let expected = reference(&b, 8, b.occupancy() + 1, b.to_play(), eval);
+                for &ordering in &[MoveOrdering::Fixed, MoveOrdering::Dynamic] {
+                    let (got, _) = search(&b, 8, &Cache::new(), eval, ordering);
+                    assert_eq!(got, expected, "position {:?}", moves);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reused_cache_matches_uncached() {
+        for moves in &POSITIONS {
+            for &eval in &EVALUATORS {
+                let b = Board::from_moves(moves).unwrap();
+                let cache = Cache::new();
+                for depth in 1..=8 {
+                    let expected = reference(&b, depth, b.occupancy() + 1, b.to_play(), eval);
+                    let (got, _) = search(&b, depth, &cache, eval, MoveOrdering::Dynamic);
+                    assert_eq!(got, expected, "position {:?} at depth {}", moves, depth);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stopped_search_leaves_the_cache_consistent() {
+        let eval = Heuristic::default();
+        for moves in &POSITIONS {
+            let b = Board::from_moves(moves).unwrap();
+            let (turn, color) = (b.occupancy() + 1, b.to_play());
+            let expected = reference(&b, 8, turn, color, &eval);
+            for &millis in &[1, 5, 20] {
+                let cache = Cache::new();
+                let mut history = History::new();
+                let mut stopped = Search {
+                    stop: &AtomicBool::new(false),
+                    deadline: Some(Instant::now() + Duration::from_millis(millis)),
+                    ..Search::for_test(&cache, &eval, &mut history)
+                };
+                negamax(&b, 8, turn, color, &mut stopped, Score::MIN, Score::MAX);
+                let (got, _) = search(&b, 8, &cache, &eval, MoveOrdering::Dynamic);
+                assert_eq!(got, expected, "position {:?} after {}ms", moves, millis);
+            }
+        }
+    }
+
+    #[test]
+    fn stopped_before_searching() {
+        let mut ai = AI::new();
+        ai.cancel.store(true, Ordering::Relaxed);
+        // Yellow must block the column of Red.
+        let b = Board::from_moves("12121").unwrap();
+        assert_eq!(ai.think(&b).unwrap().best_move, ALL_COL_IDXS[0]);
+        let analysis = ai.analyze(&b).unwrap();
+        assert_eq!(analysis.best_move, None);
+        assert_eq!(analysis.columns[0], ColumnAnalysis::Unsearched);
+        assert!(analysis.columns[1..]
+            .iter()
+            .all(|&column| column == ColumnAnalysis::ImmediateLoss));
+    }
+
+    #[test]
+    fn null_window_matches_full_window() {
+        let eval = Heuristic::default();
+        for moves in &ENDGAMES {
+            let b = Board::from_moves(moves).unwrap();
+            let turn = b.occupancy() + 1;
+            let depth = GRID_SIZE - b.occupancy();
+            let (expected, _) = search(&b, depth, &Cache::new(), &eval, MoveOrdering::Dynamic);
+            let (cache, mut history) = (Cache::new(), History::new());
+            let mut search = Search::for_test(&cache, &eval, &mut history);
+            let (col, got) = null_window_search(&b, turn, b.to_play(), &mut search);
+            assert_eq!(got, expected, "position {:?}", moves);
+            // The chosen move must reach that value.
+            let mut child = b.clone();
+            let child_score = if child.add_and_check(col.unwrap(), b.to_play()).unwrap() {
+                Score::win(22 - turn.div_ceil(2) as i8)
+            } else {
+                -AI::new().solve(&child).unwrap().score
+            };
+            assert_eq!(child_score, expected, "position {:?}", moves);
+        }
+    }
+
+    #[test]
+    fn possible_scores_of_the_last_turns() {
+        assert_eq!(possible_scores(42), [Score::new(0), Score::win(1)]);
+        assert_eq!(
+            possible_scores(41),
+            [-Score::win(1), Score::new(0), Score::win(1)]
+        );
+        let scores = possible_scores(1);
+        assert_eq!(scores.len(), 43);
+        assert!(scores.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn dynamic_ordering_searches_fewer_nodes() {
+        let eval = Heuristic::default();
+        let count = |ordering| -> u64 {
+            POSITIONS
+                .iter()
+                .map(|moves| {
+                    let b = Board::from_moves(moves).unwrap();
+                    let cache = Cache::new();
+                    (1..=10)
+                        .map(|depth| search(&b, depth, &cache, &eval, ordering).1)
+                        .sum::<u64>()
+                })
+                .sum()
+        };
+        let fixed = count(MoveOrdering::Fixed);
+        let dynamic = count(MoveOrdering::Dynamic);
+        assert!(dynamic < fixed, "{} nodes vs {} nodes", dynamic, fixed);
+    }
+
+    #[test]
+    fn parallel_matches_single_threaded() {
+        for moves in &POSITIONS {
+            let b = Board::from_moves(moves).unwrap();
+            let expected = reference(&b, 8, b.occupancy() + 1, b.to_play(), &Heuristic::default());
+            for &solver in &[Solver::FullWindow, Solver::NullWindow] {
+                let mut ai = AI::new().with_solver(solver).with_threads(4);
+                let (_, got) = ai.parallel_search(&b, 8, solver);
+                assert_eq!(got, expected, "position {:?}", moves);
+            }
+        }
+    }
+
+    #[test]
+    fn solves_known_positions() {
+        // From the test sets of Pascal Pons' Connect 4 solver, scored
+        // +-(tokens the winner has left after winning).
+        let known = [
+            ("2252576253462244111563365343671351441", -1, Outcome::Loss),
+            ("7422341735647741166133573473242566", 1, Outcome::Win),
+            ("23163416124767223154467471272416755633", 0, Outcome::Draw),
+        ];
+        let mut ai = AI::new();
+        for &(moves, pons_score, outcome) in &known {
+            let b = Board::from_moves(moves).unwrap();
+            let solution = ai.solve(&b).unwrap();
+            assert_eq!(solution.outcome, outcome, "position {:?}", moves);
+            let remaining = solution.score.winner_tokens().map_or(0, |t| 22 - t as i16);
+            assert_eq!(remaining * solution.score.get().signum(), pons_score);
+        }
+    }
+
+    #[test]
+    fn counts_moves_to_win() {
+        // Red wins by completing its bottom row right away.
+        let solution = AI::new()
+            .solve(&Board::from_moves("112233").unwrap())
+            .unwrap();
+        assert_eq!(solution.outcome, Outcome::Win);
+        assert_eq!(solution.moves_to_win, Some(1));
+        assert_eq!(solution.best_move, Some(ALL_COL_IDXS[3]));
+        // Red threatens both ends of its bottom row, Yellow can only block
+        // one.
+        let solution = AI::new()
+            .solve(&Board::from_moves("22334").unwrap())
+            .unwrap();
+        assert_eq!(solution.outcome, Outcome::Loss);
+        assert_eq!(solution.moves_to_win, Some(2));
+    }
+
+    #[test]
+    fn nothing_to_solve_once_the_game_is_over() {
+        let won = Board::from_moves("1212121").unwrap();
+        let drawn = Board::from_moves("676267454774755615735426464532211123113332").unwrap();
+        let mut ai = AI::new();
+        let already_won = MoveError::AlreadyWon {
+            winner: NonEmptySqrState::Red,
+        };
+        assert_eq!(ai.solve(&won), Err(already_won));
+        assert_eq!(ai.weak_solve(&won), Err(already_won));
+        assert_eq!(ai.analyze(&won), Err(already_won));
+        assert_eq!(ai.solve(&drawn), Err(MoveError::BoardFull));
+        assert_eq!(
+            ai.explain(&drawn, ALL_COL_IDXS[0]).reasons,
+            [Reason::ColumnFull]
+        );
+    }
+
+    /// Solving the empty board takes a long time and several gigabytes of
+    /// cache: run with `cargo test --release -- --ignored`.
+    /// [`only_the_center_wins`] checks the same from a position late enough
+    /// for every test run.
+    #[test]
+    #[ignore]
+    fn first_player_wins_in_the_center() {
+        let mut ai = AI::new();
+        let solution = ai.solve(&Board::new()).unwrap();
+        assert_eq!(solution.outcome, Outcome::Win);
+        assert_eq!(solution.best_move, Some(ALL_COL_IDXS[3]));
+        // Only the center wins, its neighbours draw and the other columns
+        // lose.
+        let expected = [
+            Outcome::Win,
+            Outcome::Win,
+            Outcome::Draw,
+            Outcome::Loss,
+            Outcome::Draw,
+            Outcome::Win,
+            Outcome::Win,
+        ];
+        for (&coli, &yellow_outcome) in ALL_COL_IDXS.iter().zip(&expected) {
+            let mut b = Board::new();
+            b.add_to_col(coli, NonEmptySqrState::Red).unwrap();
+            assert_eq!(
+                ai.solve(&b).unwrap().outcome,
+                yellow_outcome,
+                "column {}",
+                coli
+            );
+        }
+    }
+
+    /// Like [`first_player_wins_in_the_center`], with 16 empty cells, and
+    /// the outcomes of the columns checked against a tablebase, which values
+    /// every position without searching.
+    #[test]
+    fn only_the_center_wins() {
+        let root = Board::from_moves("54731552367547134256711431").unwrap();
+        let empty = GRID_SIZE - root.occupancy();
+        let tablebase = Tablebase::generate(&root, empty, MAX_POSITIONS, |_, _| {}).unwrap();
+        let mut ai = AI::new();
+        let solution = ai.solve(&root).unwrap();
+        assert_eq!(solution.outcome, Outcome::Win);
+        assert_eq!(solution.best_move, Some(ALL_COL_IDXS[3]));
+        assert_eq!(
+            Some((ALL_COL_IDXS[3], solution.score)),
+            tablebase.lookup(&root)
+        );
+        let expected = [
+            Outcome::Win,
+            Outcome::Draw,
+            Outcome::Draw,
+            Outcome::Loss,
+            Outcome::Win,
+            Outcome::Draw,
+            Outcome::Draw,
+        ];
+        for (&coli, &opponent_outcome) in ALL_COL_IDXS.iter().zip(&expected) {
+            let mut b = root.clone();
+            assert!(!b.add_and_check(coli, root.to_play()).unwrap());
+            let solution = ai.solve(&b).unwrap();
+            assert_eq!(solution.outcome, opponent_outcome, "column {}", coli);
+            assert_eq!(
+                Some(solution.score),
+                tablebase.lookup(&b).map(|(_, score)| score)
+            );
+        }
+    }
+
+    /// Positions from the end of the game, quick to solve.
+    const ENDGAMES: [&str; 6] = [
+        "2252576253462244111563365343671351441",
+        "7422341735647741166133573473242566",
+        "23163416124767223154467471272416755633",
+        "2252576253462244111563365343",
+        "74223417356477411661335734",
+        "231634161247672231544674712724",
+    ];
+
+    #[test]
+    fn weak_solve_matches_strong_solve() {
+        for moves in &ENDGAMES {
+            let b = Board::from_moves(moves).unwrap();
+            let expected = AI::new().solve(&b).unwrap().outcome;
+            assert_eq!(
+                AI::new().weak_solve(&b).unwrap(),
+                expected,
+                "position {:?}",
+                moves
+            );
+        }
+    }
+
+    #[test]
+    fn weak_solve_searches_fewer_nodes() {
+        let eval = Heuristic::default();
+        let count = |solver| -> u64 {
+            ENDGAMES
+                .iter()
+                .map(|moves| {
+                    let b = Board::from_moves(moves).unwrap();
+                    let (cache, mut history) = (Cache::new(), History::new());
+                    let mut search = Search::for_test(&cache, &eval, &mut history);
+                    iterative_deepening(&b, 1, GRID_SIZE - b.occupancy(), solver, &mut search);
+                    search.nodes
+                })
+                .sum()
+        };
+        let strong = count(Solver::FullWindow);
+        let weak = count(Solver::Weak);
+        assert!(weak < strong, "{} nodes vs {} nodes", weak, strong);
+    }
+
+    #[test]
+    fn null_window_searches_fewer_nodes() {
+        let eval = Heuristic::default();
+        let count = |solver| -> u64 {
+            ENDGAMES
+                .iter()
+                .map(|moves| {
+                    let b = Board::from_moves(moves).unwrap();
+                    let (cache, mut history) = (Cache::new(), History::new());
+                    let mut search = Search::for_test(&cache, &eval, &mut history);
+                    iterative_deepening(&b, 1, GRID_SIZE - b.occupancy(), solver, &mut search);
+                    search.nodes
+                })
+                .sum()
+        };
+        let full = count(Solver::FullWindow);
+        let null = count(Solver::NullWindow);
+        assert!(null < full, "{} nodes vs {} nodes", null, full);
+    }
+
+    #[test]
+    fn principal_variation_reaches_the_solved_result() {
+        for moves in &ENDGAMES {
+            let b = Board::from_moves(moves).unwrap();
+            let mut ai = AI::new();
+            let solution = ai.solve(&b).unwrap();
+            let pv = ai.principal_variation(&b, solution.best_move.unwrap(), ai.stats.depth);
+            let line = format!("{}{}", moves, moves_to_string(&pv));
+            let end = Board::from_moves(&line).unwrap();
+            match solution.moves_to_win {
+                Some(n) => {
+                    assert_eq!(pv.len(), n as usize, "line {:?}", line);
+                    let last = *pv.last().unwrap();
+                    let mut before = Board::from_moves(&line[..line.len() - 1]).unwrap();
+                    assert!(before.add_and_check(last, before.to_play()).unwrap());
+                }
+                None => assert_eq!(end.occupancy(), GRID_SIZE, "line {:?}", line),
+            }
+        }
+    }
+
+    #[test]
+    fn principal_variation_stops_at_the_horizon_and_at_bounds() {
+        let b = Board::new();
+        let mut ai = AI::new();
+        ai.set_position(&b);
+        let info = ai
+            .go(&Limits {
+                depth: Some(4),
+                ..Limits::default()
+            })
+            .unwrap();
+        assert_eq!(info.depth, 4);
+        assert_eq!(info.pv.len(), 4);
+        let mut child = b.clone();
+        child.add_and_check(info.best_move, b.to_play()).unwrap();
+        let entry = ai.cache.get(&child).unwrap();
+        ai.cache.store(
+            &child,
+            CacheEntry {
+                bound: Bound::Lower,
+                ..entry
+            },
+        );
+        assert_eq!(
+            ai.principal_variation(&b, info.best_move, 4),
+            vec![info.best_move]
+        );
+    }
+
+    #[test]
+    fn analysis_agrees_with_solver() {
+        for moves in &ENDGAMES {
+            let b = Board::from_moves(moves).unwrap();
+            let mut ai = AI::new();
+            let analysis = ai.analyze(&b).unwrap();
+            assert_eq!(analysis.score, ai.solve(&b).unwrap().score);
+            for (&coli, &column) in ALL_COL_IDXS.iter().zip(&analysis.columns) {
+                let mut child = b.clone();
+                let won = child.add_and_check(coli, b.to_play());
+                match column {
+                    ColumnAnalysis::Full => assert!(won.is_err()),
+                    ColumnAnalysis::ImmediateWin => assert!(won.unwrap()),
+                    ColumnAnalysis::ImmediateLoss => {
+                        assert!(!won.unwrap());
+                        assert!(winning_move(&child).is_some());
+                    }
+                    ColumnAnalysis::Searched { score, bound } => {
+                        assert!(!won.unwrap());
+                        let exact = -ai.solve(&child).unwrap().score;
+                        match bound {
+                            Bound::Exact => assert_eq!(score, exact),
+                            Bound::Upper => assert!(exact <= score && score < analysis.score),
+                            Bound::Lower => unreachable!(),
+                        }
+                    }
+                    ColumnAnalysis::Unsearched => unreachable!(),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn think_reports_search_stats() {
+        let mut ai = AI::new();
+        ai.max_depth = 8;
+        let b = Board::from_moves("4453").unwrap();
+        let info = ai.think(&b).unwrap();
+        assert_eq!(info.source, MoveSource::Search);
+        assert_eq!(info.depth, 8);
+        assert_eq!(info.pv[0], info.best_move);
+        assert!(info.tt_hits > 0 && info.tt_cutoffs <= info.tt_hits);
+        // The second time, the cache answers most of the search.
+        let again = ai.think(&b).unwrap();
+        assert_eq!(again.best_move, info.best_move);
+        assert!(again.nodes < info.nodes);
+    }
+
+    #[test]
+    fn cache_round_trip() {
+        let mut ai = AI::new();
+        let b = Board::from_moves(ENDGAMES[3]).unwrap();
+        let info = ai.think(&b).unwrap();
+        let mut file = Vec::new();
+        ai.cache.write_to(&mut file).unwrap();
+        let mut again = Vec::new();
+        ai.cache.write_to(&mut again).unwrap();
+        assert_eq!(file, again);
+
+        let mut loaded = AI::with_evaluator(Box::new(Neutral));
+        let len = loaded.cache.read_from(&file[..]).unwrap();
+        assert!(len > 0);
+        assert_eq!(len, (file.len() - 25) / CACHE_ENTRY_SIZE);
+        let mut written = Vec::new();
+        loaded.cache.write_to(&mut written).unwrap();
+        assert_eq!(written, file);
+        let reloaded = loaded.think(&b).unwrap();
+        assert_eq!(reloaded.best_move, info.best_move);
+        assert_eq!(reloaded.score, info.score);
+        assert!(reloaded.nodes < info.nodes);
+    }
+
+    #[test]
+    fn only_solved_entries_are_saved() {
+        let mut ai = AI::new();
+        ai.max_depth = 6;
+        ai.think(&Board::from_moves("4453").unwrap()).unwrap();
+        assert!(ai.cache_stats().entries > 0);
+        let mut file = Vec::new();
+        ai.cache.write_to(&mut file).unwrap();
+        let len = Cache::new().read_from(&file[..]).unwrap();
+        let solved = ai.cache.shards.iter().map(|shard| {
+            let shard = shard.lock().unwrap();
+            shard
+                .slots
+                .iter()
+                .filter(|(b, slot)| slot.entry.is_solved(b))
+                .count()
+        });
+        assert_eq!(len, solved.sum::<usize>());
+        assert!(len < ai.cache_stats().entries);
+    }
+
+    #[test]
+    fn solved_draws_are_saved() {
+        let b = Board::from_moves("23163416124767223154467471272416755633").unwrap();
+        let mut ai = AI::new();
+        assert_eq!(ai.solve(&b).unwrap().score, Score::new(0));
+        let mut file = Vec::new();
+        ai.cache.write_to(&mut file).unwrap();
+        let loaded = Cache::new();
+        loaded.read_from(&file[..]).unwrap();
+        let entry = loaded.get(&b).unwrap();
+        assert_eq!(entry.bound, Bound::Exact);
+        assert_eq!(entry.score, Score::new(0));
+    }
+
+    #[test]
+    fn new_game_ages_the_cache() {
+        let mut ai = AI::new();
+        ai.max_depth = 6;
+        ai.think(&Board::from_moves("4453").unwrap()).unwrap();
+        let solved = Board::from_moves("231634161247672231544674712724").unwrap();
+        ai.solve(&solved).unwrap();
+        let stats = ai.cache_stats();
+        assert_eq!(stats.games, 1);
+        assert!(stats.hits > 0 && stats.hits <= stats.probes);
+        assert_eq!(stats.carried_hits, 0);
+        // Entries last used in the previous game are kept.
+        ai.new_game();
+        assert_eq!(ai.cache_stats().entries, stats.entries);
+        ai.new_game();
+        let aged = ai.cache_stats();
+        assert_eq!(aged.games, 3);
+        assert!(aged.entries > 0 && aged.entries < stats.entries);
+        assert_eq!(aged.aged_out as usize, stats.entries - aged.entries);
+        for shard in &ai.cache.shards {
+            let shard = shard.lock().unwrap();
+            assert!(shard
+                .slots
+                .values()
+                .all(|slot| slot.entry.score.is_proven()));
+        }
+        ai.stats = SearchStats::default();
+        let solution = ai.solve(&solved).unwrap();
+        assert!(ai.cache_stats().carried_hits > 0);
+        let mut fresh = AI::new();
+        assert_eq!(solution.score, fresh.solve(&solved).unwrap().score);
+        assert!(ai.stats.nodes < fresh.stats.nodes);
+        for _ in 0..=PROVEN_MAX_AGE {
+            ai.new_game();
+        }
+        assert_eq!(ai.cache_stats().entries, 0);
+    }
+
+    #[test]
+    fn corrupted_cache_is_rejected() {
+        let mut ai = AI::new();
+        ai.solve(&Board::from_moves(ENDGAMES[5]).unwrap()).unwrap();
+        let mut file = Vec::new();
+        ai.cache.write_to(&mut file).unwrap();
+        assert!(file.len() > 25);
+        let mut version = file.clone();
+        version[8] += 1;
+        let mut flipped = file.clone();
+        flipped[20] ^= 1;
+        let truncated = &file[..file.len() - 1];
+        // An evaluator-dependent bound, with a checksum to match.
+        let mut unsolved = file[..17 + CACHE_ENTRY_SIZE].to_vec();
+        unsolved[9..17].copy_from_slice(&1u64.to_le_bytes());
+        unsolved[17 + 11] = Bound::Lower as u8;
+        let checksum = fnv1a(FNV_OFFSET, &unsolved[17..]);
+        unsolved.extend_from_slice(&checksum.to_le_bytes());
+        for bad in &[&version[..], &flipped[..], truncated, &unsolved[..]] {
+            let cache = Cache::new();
+            assert!(cache.read_from(*bad).is_err());
+            let mut empty = Vec::new();
+            cache.write_to(&mut empty).unwrap();
+            assert_eq!(empty.len(), 25);
+        }
+    }
+
+    #[test]
+    fn pondering_prepares_the_reply() {
+        let b = Board::from_moves("445").unwrap();
+        let reply = Board::from_moves("4453").unwrap();
+        let mut fresh = AI::new();
+        fresh.max_depth = 8;
+        let expected = fresh.think(&reply).unwrap();
+        let mut pondering = AI::new();
+        pondering.max_depth = 8;
+        pondering.ponder(&b);
+        let info = pondering.think(&reply).unwrap();
+        assert_eq!(info.score, expected.score);
+        assert!(info.nodes < expected.nodes / 2);
+    }
+
+    /// The moves of a game between `red_moves` and `yellow`, which first
+    /// analyses each position for a hint if `hints`.
+    fn play_game(mut yellow: AI, red_moves: &str, hints: bool) -> String {
+        let mut b = Board::new();
+        let mut moves = String::new();
+        for mv in red_moves.chars() {
+            let coli = Board::check_col_idx(mv.to_digit(10).unwrap() as u8 - 1).unwrap();
+            match b.add_and_check(coli, NonEmptySqrState::Red) {
+                Ok(false) => moves.push(mv),
+                Ok(true) => {
+                    moves.push(mv);
+                    break;
+                }
+                Err(_) => break,
+            }
+            if hints {
+                yellow.set_position(&b);
+                Engine::analyze(&mut yellow, &Limits::default()).unwrap();
+            }
+            let coli = yellow.make_a_move(&b).unwrap();
+            moves.push_str(&moves_to_string(&[coli]));
+            if b.add_and_check(coli, NonEmptySqrState::Yellow).unwrap() {
+                break;
+            }
+        }
+        moves
+    }
+
+    #[test]
+    fn difficulty_is_reproducible() {
+        let red = "4455667";
+        let game = |seed, hints| {
+            play_game(
+                AI::new().with_difficulty(Difficulty::Beginner, seed),
+                red,
+                hints,
+            )
+        };
+        assert_eq!(game(1, false), game(1, false));
+        let games: Vec<_> = (0..8).map(|seed| game(seed, false)).collect();
+        assert!(games.iter().any(|g| *g != games[0]));
+    }
+
+    #[test]
+    fn hints_do_not_change_the_game() {
+        for seed in 0..4 {
+            let game = |hints| {
+                play_game(
+                    AI::new().with_difficulty(Difficulty::Beginner, seed),
+                    "4455667",
+                    hints,
+                )
+            };
+            assert_eq!(game(true), game(false), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn suboptimal_moves_do_not_lose() {
+        for moves in &ENDGAMES {
+            let b = Board::from_moves(moves).unwrap();
+            let mut ai = AI::new();
+            if ai.solve(&b).unwrap().outcome == Outcome::Loss {
+                continue;
+            }
+            let mut analysis = ai.analyze(&b).unwrap();
+            ai.resolve_bounds(&b, &mut analysis);
+            for (coli, _) in suboptimal_candidates(&analysis) {
+                let mut child = b.clone();
+                child.add_to_col(coli, b.to_play()).unwrap();
+                assert_ne!(ai.solve(&child).unwrap().outcome, Outcome::Win);
+            }
+        }
+    }
+
+    /// The moves the search sees losing: for the easy level, within 4 plies.
+    #[test]
+    fn deviations_never_lose_within_the_horizon() {
+        let b = Board::from_moves("7277351534577712245").unwrap();
+        let depth = Difficulty::Easy.max_depth();
+        let loses = |coli| {
+            let mut child = b.clone();
+            matches!(child.add_and_check(coli, b.to_play()), Ok(false)) && {
+                let eval = Heuristic::default();
+                let turn = child.occupancy() + 1;
+                let score = reference(&child, depth - 1, turn, child.to_play(), &eval);
+                score.is_proven() && score.get() > 0
+            }
+        };
+        let losing: Vec<_> = COLS_ORDER.iter().copied().filter(|&c| loses(c)).collect();
+        assert_eq!(losing.len(), 4);
+        let mut deviations = 0;
+        for seed in 0..32 {
+            let mut ai = AI::new().with_difficulty(Difficulty::Easy, seed);
+            let info = ai.think(&b).unwrap();
+            assert!(!losing.contains(&info.best_move), "seed {}", seed);
+            deviations += (info.source == MoveSource::Deviation) as usize;
+        }
+        assert!(deviations > 0);
+    }
+
+    #[test]
+    fn weakened_ai_deviates_from_the_book() {
+        let b = Board::from_moves(ENDGAMES[5]).unwrap();
+        let book = Book::generate(&b, b.occupancy(), &mut AI::new(), |_, _| {});
+        let mut deviations = 0;
+        for seed in 0..16 {
+            let mut ai = AI::new()
+                .with_book(book.clone())
+                .with_difficulty(Difficulty::Beginner, seed);
+            let info = ai.think(&b).unwrap();
+            match info.source {
+                MoveSource::Deviation => deviations += 1,
+                source => assert_eq!(source, MoveSource::Book, "seed {}", seed),
+            }
+        }
+        assert!(deviations > 0);
+    }
+}
@@ -0,0 +1,411 @@
+//! Matches between two engine configurations, to tell objectively whether
+//! a change makes an engine stronger.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::ai::{Outcome, AI};
+use crate::difficulty::Difficulty;
+use crate::engine::{engine_by_name, Engine, Limits, ENGINE_NAMES};
+use crate::packedboard::*;
+use crate::rng::Rng;
+
+/// Number of moves of the openings the games of a match start from.
+pub const OPENING_PLIES: u8 = 3;
+
+/// An engine with its settings, written `ENGINE[:KEY=VALUE,...]` with the
+/// keys `level` (negamax only), `depth`, `iterations` and `time` (in
+/// milliseconds), e.g. `negamax:level=hard` or `mcts:iterations=5000`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Player {
+    pub engine: String,
+    pub level: Option<Difficulty>,
+    pub limits: Limits,
+}
+
+impl Player {
+    /// A new engine with these settings.
+    pub fn build(&self, seed: u64) -> Box<dyn Engine> {
+        match self.level {
+            Some(level) => Box::new(AI::new().with_difficulty(level, seed)),
+            None => engine_by_name(&self.engine, seed).unwrap(),
+        }
+    }
+}
+
+impl fmt::Display for Player {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut settings = Vec::new();
+        if let Some(level) = self.level {
+            settings.push(format!("level={}", level));
+        }
+        if let Some(depth) = self.limits.depth {
+            settings.push(format!("depth={}", depth));
+        }
+        if let Some(iterations) = self.limits.iterations {
+            settings.push(format!("iterations={}", iterations));
+        }
+        if let Some(time) = self.limits.time {
+            settings.push(format!("time={}", time.as_millis()));
+        }
+        f.write_str(&self.engine)?;
+        if !settings.is_empty() {
+            write!(f, ":{}", settings.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidPlayer(String);
+
+impl fmt::Display for InvalidPlayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "Invalid player {:?}, expected ENGINE[:KEY=VALUE,...] with ENGINE one of {} \
+             and KEY one of level, depth, iterations, time",
+            self.0,
+            ENGINE_NAMES.join(", ")
+        ))
+    }
+}
+
+impl std::error::Error for InvalidPlayer {}
+
+impl FromStr for Player {
+    type Err = InvalidPlayer;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidPlayer(s.to_owned());
+        let (engine, settings) = match s.split_once(':') {
+            Some((engine, settings)) => (engine, Some(settings)),
+            None => (s, None),
+        };
+        if !ENGINE_NAMES.contains(&engine) {
+            return Err(invalid());
+        }
+        let mut player = Player {
+            engine: engine.to_owned(),
+            level: None,
+            limits: Limits::default(),
+        };
+        for setting in settings.into_iter().flat_map(|s| s.split(',')) {
+            let (key, value) = setting.split_once('=').ok_or_else(invalid)?;
+            match key {
+                "level" if engine == "negamax" => {
+                    player.level = Some(value.parse().map_err(|_| invalid())?)
+                }
+                "depth" => player.limits.depth = Some(value.parse().map_err(|_| invalid())?),
+                "iterations" => {
+                    player.limits.iterations = Some(value.parse().map_err(|_| invalid())?)
+                }
+                "time" => {
+                    let millis = value.parse().map_err(|_| invalid())?;
+                    player.limits.time = Some(Duration::from_millis(millis))
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(player)
+    }
+}
+
+/// Every position after `plies` moves that is not already won, once for
+/// each pair of mirror images, shuffled by `seed`.
+pub fn openings(plies: u8, seed: u64) -> Vec<Board> {
+    let mut positions = vec![Board::new()];
+    for _ in 0..plies {
+        positions = next_positions(&positions);
+    }
+    // Fisher-Yates.
+    let mut rng = Rng::new(seed);
+    for i in (1..positions.len()).rev() {
+        positions.swap(i, rng.below(i + 1));
+    }
+    positions
+}
+
+/// Plays a game from `opening`, `players[0]` moving first, and returns the
+/// winner if any.
+pub fn play_game(
+    mut players: [(&mut dyn Engine, &Limits); 2],
+    opening: &Board,
+) -> Option<NonEmptySqrState> {
+    for (engine, _) in players.iter_mut() {
+        engine.new_game();
+    }
+    let mut b = opening.clone();
+    let first_color = b.to_play();
+    while b.occupancy() < GRID_SIZE {
+        let color = b.to_play();
+        let (engine, limits) = &mut players[(color != first_color) as usize];
+        engine.set_position(&b);
+        let coli = engine.go(limits).expect("the game is not over").best_move;
+        if b.add_and_check(coli, color).expect("illegal move") {
+            return Some(color);
+        }
+    }
+    None
+}
+
+/// Games won, drawn and lost by the first player of a match.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MatchResult {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+/// Sequential probability ratio test of whether the first player of a match
+/// is `elo1` stronger than the second (H1) rather than `elo0` (H0), with
+/// false positive and false negative rates `alpha` and `beta`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+impl Default for Sprt {
+    fn default() -> Self {
+        Self {
+            elo0: 0.0,
+            elo1: 10.0,
+            alpha: 0.05,
+            beta: 0.05,
+        }
+    }
+}
+
+impl Sprt {
+    /// The log-likelihood ratio below which H0 is accepted, and the one
+    /// above which H1 is.
+    pub fn bounds(&self) -> (f64, f64) {
+        (
+            (self.beta / (1.0 - self.alpha)).ln(),
+            ((1.0 - self.beta) / self.alpha).ln(),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SprtVerdict {
+    /// The first player is at least `elo1` stronger.
+    AcceptH1,
+    /// The first player is at most `elo0` stronger.
+    AcceptH0,
+    /// More games are needed.
+    Continue,
+}
+
+/// Expected score of a player `elo` stronger than their opponent.
+fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+/// Elo difference of a player expected to score `score`.
+fn elo_difference(score: f64) -> f64 {
+    400.0 * (score / (1.0 - score)).log10()
+}
+
+impl MatchResult {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    fn add(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Win => self.wins += 1,
+            Outcome::Draw => self.draws += 1,
+            Outcome::Loss => self.losses += 1,
+        }
+    }
+
+    /// Average points per game, draws counting for half.
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games() as f64
+    }
+
+    /// Variance of the points of one game.
+    fn variance(&self) -> f64 {
+        let s = self.score();
+        let n = self.games() as f64;
+        (self.wins as f64 * (1.0 - s).powi(2)
+            + self.draws as f64 * (0.5 - s).powi(2)
+            + self.losses as f64 * s.powi(2))
+            / n
+    }
+
+    /// Elo difference between the players, with the half-width of its 95%
+    /// confidence interval. None while a player won or drew every game.
+    pub fn elo(&self) -> Option<(f64, f64)> {
+        let s = self.score();
+        if self.games() == 0 || s <= 0.0 || s >= 1.0 {
+            return None;
+        }
+        let margin = 1.96 * (self.variance() / self.games() as f64).sqrt();
+        let low = elo_difference((s - margin).max(f64::MIN_POSITIVE));
+        let high = elo_difference((s + margin).min(1.0 - f64::EPSILON));
+        Some((elo_difference(s), (high - low) / 2.0))
+    }
+
+    /// Log-likelihood ratio of H1 to H0 of `sprt`, in the normal
+    /// approximation of the points of a game.
+    pub fn llr(&self, sprt: &Sprt) -> f64 {
+        let variance = self.variance();
+        if self.games() == 0 || variance == 0.0 {
+            return 0.0;
+        }
+        let (s0, s1) = (expected_score(sprt.elo0), expected_score(sprt.elo1));
+        let n = self.games() as f64;
+        let points = self.score() * n;
+        (s1 - s0) * (2.0 * points - n * (s0 + s1)) / (2.0 * variance)
+    }
+
+    pub fn sprt(&self, sprt: &Sprt) -> SprtVerdict {
+        let llr = self.llr(sprt);
+        let (lower, upper) = sprt.bounds();
+        if llr >= upper {
+            SprtVerdict::AcceptH1
+        } else if llr <= lower {
+            SprtVerdict::AcceptH0
+        } else {
+            SprtVerdict::Continue
+        }
+    }
+}
+
+impl fmt::Display for MatchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "+{} ={} -{} ({:.1}%)",
+            self.wins,
+            self.draws,
+            self.losses,
+            100.0 * self.score()
+        )?;
+        match self.elo() {
+            Some((elo, margin)) => write!(f, ", Elo {:+.1} ± {:.1}", elo, margin),
+            None => f.write_str(", Elo unknown"),
+        }
+    }
+}
+
+/// Plays `first` against `second` over at most `pairs` pairs of games, each
+/// pair starting from one of the `openings` (used in turn) with both
+/// players moving first once, and stops early once `sprt` concludes.
+/// `progress` is called after each pair.
+pub fn run_match(
+    first: &Player,
+    second: &Player,
+    openings: &[Board],
+    pairs: usize,
+    sprt: &Sprt,
+    mut progress: impl FnMut(&MatchResult),
+) -> MatchResult {
+    let mut result = MatchResult::default();
+    for (pair, opening) in openings.iter().cycle().take(pairs).enumerate() {
+        for swap in [false, true] {
+            let seed = 2 * pair as u64 + swap as u64;
+            let (mut a, mut b) = (first.build(seed), second.build(seed));
+            let (a_limits, b_limits) = (&first.limits, &second.limits);
+            let winner = if swap {
+                play_game([(&mut *b, b_limits), (&mut *a, a_limits)], opening)
+            } else {
+                play_game([(&mut *a, a_limits), (&mut *b, b_limits)], opening)
+            };
+            let first_color = if swap {
+                opening.to_play().other()
+            } else {
+                opening.to_play()
+            };
+            result.add(match winner {
+                None => Outcome::Draw,
+                Some(color) if color == first_color => Outcome::Win,
+                Some(_) => Outcome::Loss,
+            });
+        }
+        progress(&result);
+        if result.sprt(sprt) != SprtVerdict::Continue {
+            break;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_players() {
+        let player: Player = "negamax:level=hard,depth=6".parse().unwrap();
+        assert_eq!(player.level, Some(Difficulty::Hard));
+        assert_eq!(player.limits.depth, Some(6));
+        assert_eq!(player.to_string().parse::<Player>().unwrap(), player);
+        let player: Player = "mcts:iterations=500,time=20".parse().unwrap();
+        assert_eq!(player.limits.iterations, Some(500));
+        assert_eq!(player.limits.time, Some(Duration::from_millis(20)));
+        for invalid in &[
+            "alphazero",
+            "mcts:level=hard",
+            "negamax:depth",
+            "negamax:speed=1",
+        ] {
+            assert!(invalid.parse::<Player>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn openings_are_distinct() {
+        let openings = openings(2, 0);
+        // 49 positions, of which only "44" is its own mirror image.
+        assert_eq!(openings.len(), 1 + 48 / 2);
+        assert!(openings.iter().all(|b| b.occupancy() == 2));
+        assert_ne!(openings, super::openings(2, 1));
+    }
+
+    #[test]
+    fn elo_and_sprt() {
+        let result = MatchResult {
+            wins: 600,
+            draws: 200,
+            losses: 200,
+        };
+        let (elo, margin) = result.elo().unwrap();
+        assert!((elo - 147.2).abs() < 0.1, "{}", elo);
+        assert!(0.0 < margin && margin < elo);
+        assert_eq!(result.sprt(&Sprt::default()), SprtVerdict::AcceptH1);
+        let reversed = MatchResult {
+            wins: 200,
+            draws: 200,
+            losses: 600,
+        };
+        assert!((reversed.elo().unwrap().0 + elo).abs() < 1e-9);
+        assert_eq!(reversed.sprt(&Sprt::default()), SprtVerdict::AcceptH0);
+        let even = MatchResult {
+            wins: 3,
+            draws: 2,
+            losses: 3,
+        };
+        assert_eq!(even.elo().unwrap().0, 0.0);
+        assert_eq!(even.sprt(&Sprt::default()), SprtVerdict::Continue);
+        assert_eq!(MatchResult::default().elo(), None);
+    }
+
+    #[test]
+    fn deeper_search_wins() {
+        let deep: Player = "negamax:depth=6".parse().unwrap();
+        let shallow: Player = "negamax:depth=1".parse().unwrap();
+        let openings = openings(OPENING_PLIES, 0);
+        let mut pairs = 0;
+        let result = run_match(&deep, &shallow, &openings, 4, &Sprt::default(), |_| {
+            pairs += 1
+        });
+        assert_eq!(result.games(), 2 * pairs);
+        assert!(result.score() > 0.5, "{}", result);
+    }
+}
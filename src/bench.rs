@@ -0,0 +1,229 @@
+//! Benchmark of the solver on positions of known value, in the common
+//! "moves score" format: one position per line, the moves played from the
+//! empty board then the score of the player to move, positive if they win,
+//! negative if they lose, 0 for a draw. The absolute value of a win is 22
+//! minus the number of tokens the winner plays, the winning one included.
+
+use std::fmt;
+use std::io::{self, BufRead};
+use std::time::Duration;
+
+use crate::ai::{Score, AI};
+use crate::engine::{Engine, Limits};
+use crate::packedboard::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchPosition {
+    pub moves: String,
+    pub board: Board,
+    pub expected: i8,
+}
+
+#[derive(Debug)]
+pub enum BenchError {
+    Io(io::Error),
+    InvalidLine { line: usize, content: String },
+}
+
+impl fmt::Display for BenchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BenchError::Io(e) => e.fmt(f),
+            BenchError::InvalidLine { line, content } => f.write_fmt(format_args!(
+                "Line {} is not a \"moves score\" position: {:?}",
+                line, content
+            )),
+        }
+    }
+}
+
+impl std::error::Error for BenchError {}
+
+impl From<io::Error> for BenchError {
+    fn from(e: io::Error) -> Self {
+        BenchError::Io(e)
+    }
+}
+
+/// Reads the positions of `r`, skipping blank lines.
+pub fn read_positions(r: impl BufRead) -> Result<Vec<BenchPosition>, BenchError> {
+    let mut positions = Vec::new();
+    for (index, line) in r.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let invalid = || BenchError::InvalidLine {
+            line: index + 1,
+            content: line.clone(),
+        };
+        let mut fields = line.split_whitespace();
+        let (moves, expected) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(moves), Some(score), None) => (moves, score),
+            _ => return Err(invalid()),
+        };
+        let board = Board::from_moves(moves).map_err(|_| invalid())?;
+        let expected = expected.parse().map_err(|_| invalid())?;
+        if board.occupancy() == GRID_SIZE || winning_move_played(moves) {
+            return Err(invalid());
+        }
+        positions.push(BenchPosition {
+            moves: moves.to_owned(),
+            board,
+            expected,
+        });
+    }
+    Ok(positions)
+}
+
+/// Whether the last of `moves` won the game, [`Board::from_moves`] only
+/// rejecting the moves after a win.
+fn winning_move_played(moves: &str) -> bool {
+    let (last, before) = match moves.chars().last() {
+        Some(last) => (last, &moves[..moves.len() - 1]),
+        None => return false,
+    };
+    let mut b = Board::from_moves(before).unwrap();
+    let coli = ColIdx::new(last.to_digit(10).unwrap() as u8 - 1).unwrap();
+    b.add_and_check(coli, b.to_play()).unwrap()
+}
+
+/// `score`, a proven one or a draw, in the "moves score" convention.
+pub fn position_score(score: Score) -> i8 {
+    match score.winner_tokens() {
+        Some(tokens) => (22 - tokens as i8) * score.get().signum() as i8,
+        None => 0,
+    }
+}
+
+/// A position solved to the wrong score.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub moves: String,
+    pub expected: i8,
+    pub got: i8,
+}
+
+/// Results of the positions of one difficulty, usually one file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BucketReport {
+    pub name: String,
+    /// Threads of the AI, see [`AI::with_threads`].
+    pub threads: usize,
+    pub positions: usize,
+    pub time: Duration,
+    pub nodes: u64,
+    pub failures: Vec<Failure>,
+}
+
+impl BucketReport {
+    pub fn mean_time(&self) -> Duration {
+        self.time / self.positions.max(1) as u32
+    }
+
+    pub fn mean_nodes(&self) -> f64 {
+        self.nodes as f64 / self.positions.max(1) as f64
+    }
+
+    /// How many times faster than `other` the positions were solved.
+    pub fn speedup_over(&self, other: &BucketReport) -> f64 {
+        other.time.as_secs_f64() / self.time.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+impl fmt::Display for BucketReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({} threads): {} positions, mean time {:.3} ms, mean nodes {:.0}, {} failures",
+            self.name,
+            self.threads,
+            self.positions,
+            self.mean_time().as_secs_f64() * 1000.0,
+            self.mean_nodes(),
+            self.failures.len()
+        )
+    }
+}
+
+/// Solves every position with a new AI searching with `threads` threads, so
+/// that the cache of one does not help the next, and checks its score.
+pub fn run_bucket(name: &str, positions: &[BenchPosition], threads: usize) -> BucketReport {
+    let mut report = BucketReport {
+        name: name.to_owned(),
+        threads,
+        positions: positions.len(),
+        ..BucketReport::default()
+    };
+    for position in positions {
+        let mut ai = AI::new().with_threads(threads);
+        ai.set_position(&position.board);
+        let info = ai.go(&Limits {
+            depth: Some(GRID_SIZE - position.board.occupancy()),
+            ..Limits::default()
+        });
+        // Full and already won positions are not read.
+        let info = info.unwrap();
+        report.time += info.elapsed;
+        report.nodes += info.nodes;
+        let got = info.score.map_or(0, position_score);
+        if got != position.expected {
+            report.failures.push(Failure {
+                moves: position.moves.clone(),
+                expected: position.expected,
+                got,
+            });
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POSITIONS: &str = "\
+112233 18
+2252576253462244111563365343671351441 -1
+
+7422341735647741166133573473242566 1
+";
+
+    #[test]
+    fn reads_positions() {
+        let positions = read_positions(POSITIONS.as_bytes()).unwrap();
+        assert_eq!(positions.len(), 3);
+        assert_eq!(positions[0].board, Board::from_moves("112233").unwrap());
+        assert_eq!(positions[2].expected, 1);
+        for invalid in &["1122 3 4", "1182 0", "11 x", "1213141 3", "12"] {
+            let err = read_positions(invalid.as_bytes()).unwrap_err();
+            assert!(
+                matches!(err, BenchError::InvalidLine { line: 1, .. }),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn checks_scores() {
+        let mut positions = read_positions(POSITIONS.as_bytes()).unwrap();
+        for threads in 1..=2 {
+            let report = run_bucket("sample", &positions, threads);
+            assert_eq!(report.positions, 3);
+            assert_eq!(report.threads, threads);
+            assert!(report.failures.is_empty(), "{:?}", report.failures);
+            assert!(report.nodes > 0);
+        }
+        positions[1].expected = 1;
+        let report = run_bucket("wrong", &positions, 1);
+        assert_eq!(
+            report.failures,
+            vec![Failure {
+                moves: positions[1].moves.clone(),
+                expected: 1,
+                got: -1,
+            }]
+        );
+    }
+}
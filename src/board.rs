@@ -1,5 +1,4 @@
 use array_init::array_init;
-use bv::BitVec;
 use std::char;
 use std::fmt;
 
@@ -31,8 +30,9 @@ impl std::fmt::Display for NonEmptySqrState {
     }
 }
 
-#[derive(Eq, PartialEq, Copy, Clone, Debug, Hash)]
+#[derive(Eq, PartialEq, Copy, Clone, Debug, Hash, Default)]
 pub enum SqrState {
+    #[default]
     Empty,
     NonEmpty(NonEmptySqrState),
 }
@@ -51,12 +51,6 @@ impl SqrState {
     }
 }
 
-impl Default for SqrState {
-    fn default() -> SqrState {
-        SqrState::Empty
-    }
-}
-
 impl fmt::Display for SqrState {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.to_char())
@@ -100,6 +94,10 @@ impl<const SIZE: usize> Column<SIZE> {
         self.len
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
     fn try_push(&mut self, x: NonEmptySqrState) -> Result<usize, ColumnError> {
         let prev_len = self.len as usize;
         if prev_len == SIZE {
@@ -112,11 +110,7 @@ impl<const SIZE: usize> Column<SIZE> {
     }
 
     fn get(&self, i: usize) -> Option<SqrState> {
-        self.inner.get(i).map(|&x| x)
-    }
-
-    fn get_mut(&mut self, i: usize) -> Option<&mut SqrState> {
-        self.inner.get_mut(i)
+        self.inner.get(i).copied()
     }
 }
 
@@ -214,17 +208,10 @@ impl<const COLS: usize, const ROWS: usize> Board<COLS, ROWS> {
         })
     }
 
-    fn get_cell_mut(&mut self, coli: usize, rowi: usize) -> BoardResult<&mut SqrState> {
-        let col = self.get_col_mut(coli)?;
-        col.get_mut(rowi).ok_or(BoardError::RowIndexOutOfBounds {
-            required_index: rowi,
-            row_size: COLS,
-        })
-    }
-
     ///Add token to a column. Panics if:
-    ///  - `coli` is out of bounds
-    ///  - the column is full
+    ///   - `coli` is out of bounds
+    ///   - the column is full
+    ///
     /// Returns: height of added token
     pub fn try_add_to_col(
         &mut self,
@@ -242,8 +229,9 @@ impl<const COLS: usize, const ROWS: usize> Board<COLS, ROWS> {
     }
 
     ///Add token to a column. Panics if:
-    ///  - `coli` is out of bounds
-    ///  - the column is full
+    ///   - `coli` is out of bounds
+    ///   - the column is full
+    ///
     /// Returns: height of added token
     pub fn add_to_col(&mut self, coli: usize, color: NonEmptySqrState) -> usize {
         self.try_add_to_col(coli, color).unwrap()
@@ -253,7 +241,7 @@ impl<const COLS: usize, const ROWS: usize> Board<COLS, ROWS> {
     pub fn try_win_at(&self, col_i: usize, row_i: usize) -> BoardResult<(SqrState, bool)> {
         let state = self.get_cell(col_i, row_i)?;
         match state {
-            SqrState::Empty => return Ok((state, false)),
+            SqrState::Empty => Ok((state, false)),
             SqrState::NonEmpty(_) => {
                 for (cdir, rdir) in &[(1, 0), (1, 1), (0, 1), (-1, 1)] {
                     let mut count = 0;
@@ -271,7 +259,7 @@ impl<const COLS: usize, const ROWS: usize> Board<COLS, ROWS> {
                         return Ok((state, true));
                     }
                 }
-                return Ok((state, false));
+                Ok((state, false))
             }
         }
     }
@@ -301,6 +289,12 @@ impl<const COLS: usize, const ROWS: usize> Board<COLS, ROWS> {
     }
 }
 
+impl<const COLS: usize, const ROWS: usize> Default for Board<COLS, ROWS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 //impl Index<usize> for Board {
 //    type Output = Vec<SqrState>;
 //    fn index(&self, index: usize) -> &Self::Output {&self.inner[index]}
@@ -0,0 +1,144 @@
+//! Opening book: the exact value and a best move for every position up to
+//! some ply, stored once for each pair of mirrored positions.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::ai::{Score, AI};
+use crate::packedboard::*;
+use crate::solved::SolvedTable;
+
+const MAGIC: &[u8; 8] = b"F4RBOOK\0";
+const VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Book {
+    max_ply: u8,
+    positions: SolvedTable,
+}
+
+impl Book {
+    /// Solves every position reachable from `root` that is not already won
+    /// and holds at most `max_ply` tokens. `progress` is called with each
+    /// ply before it is solved, and the number of positions in it.
+    pub fn generate(
+        root: &Board,
+        max_ply: u8,
+        ai: &mut AI,
+        mut progress: impl FnMut(u8, usize),
+    ) -> Self {
+        let mut plies = vec![vec![root.clone()]];
+        while (root.occupancy() as usize + plies.len()) <= max_ply as usize {
+            let next = next_positions(plies.last().unwrap());
+            plies.push(next);
+        }
+        // The deepest positions are the quickest to solve, and fill the cache
+        // for the shallower ones.
+        let mut solved = Vec::new();
+        for positions in plies.iter().rev() {
+            if let Some(b) = positions.first() {
+                progress(b.occupancy(), positions.len());
+            }
+            // Only `root` may be over already.
+            for b in positions {
+                if let Ok(solution) = ai.solve(b) {
+                    solved.push((b.clone(), solution.best_move.unwrap(), solution.score));
+                }
+            }
+        }
+        Self {
+            max_ply,
+            positions: SolvedTable::new(solved),
+        }
+    }
+
+    pub fn max_ply(&self) -> u8 {
+        self.max_ply
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Best move and exact value of `b`, if it is in the book.
+    pub fn lookup(&self, b: &Board) -> Option<(ColIdx, Score)> {
+        self.positions.lookup(b)
+    }
+
+    /// Writes the book: a header made of a magic string, the format version,
+    /// the maximum ply and the number of entries, then 11 bytes per entry.
+    pub fn write_to(&self, w: impl Write) -> io::Result<()> {
+        self.positions.write_to(w, MAGIC, VERSION, self.max_ply)
+    }
+
+    pub fn read_from(r: impl Read) -> io::Result<Self> {
+        let (max_ply, positions) = SolvedTable::read_from(r, MAGIC, VERSION, "opening book")?;
+        Ok(Self { max_ply, positions })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solved::{ENTRY_SIZE, HEADER_SIZE};
+
+    const ROOT: &str = "2252576253462244111563365343671351";
+
+    #[test]
+    fn lookup_finds_mirrored_positions() {
+        let root = Board::from_moves(ROOT).unwrap();
+        let mut ai = AI::new();
+        let book = Book::generate(&root, root.occupancy() + 2, &mut ai, |_, _| {});
+        assert!(book.len() > 1);
+        for b in &[root.clone(), root.mirrored()] {
+            let (col, score) = book.lookup(b).unwrap();
+            assert_eq!(ai.solve(b).unwrap().score, score);
+            let mut child = b.clone();
+            if !child.add_and_check(col, b.to_play()).unwrap() {
+                assert_eq!(-ai.solve(&child).unwrap().score, score);
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let root = Board::from_moves(ROOT).unwrap();
+        let book = Book::generate(&root, root.occupancy() + 1, &mut AI::new(), |_, _| {});
+        let mut bytes = Vec::new();
+        book.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), HEADER_SIZE + ENTRY_SIZE * book.len());
+        assert_eq!(Book::read_from(&bytes[..]).unwrap(), book);
+        bytes[0] = b'X';
+        assert!(Book::read_from(&bytes[..]).is_err());
+    }
+
+    #[test]
+    fn corrupt_header() {
+        let mut bytes = Vec::new();
+        Book::generate(
+            &Board::from_moves(ROOT).unwrap(),
+            0,
+            &mut AI::new(),
+            |_, _| {},
+        )
+        .write_to(&mut bytes)
+        .unwrap();
+        bytes[10..18].copy_from_slice(&u64::MAX.to_le_bytes());
+        let err = Book::read_from(&bytes[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(Book::read_from(&bytes[..12]).is_err());
+    }
+}
@@ -0,0 +1,107 @@
+//! Named strength levels for the AI, from easily beaten to as strong as it
+//! gets.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Difficulty {
+    Beginner,
+    Easy,
+    Medium,
+    Hard,
+    Perfect,
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 5] = [
+        Difficulty::Beginner,
+        Difficulty::Easy,
+        Difficulty::Medium,
+        Difficulty::Hard,
+        Difficulty::Perfect,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Difficulty::Beginner => "beginner",
+            Difficulty::Easy => "easy",
+            Difficulty::Medium => "medium",
+            Difficulty::Hard => "hard",
+            Difficulty::Perfect => "perfect",
+        }
+    }
+
+    /// How many plies ahead the AI looks.
+    pub fn max_depth(self) -> u8 {
+        match self {
+            Difficulty::Beginner => 2,
+            Difficulty::Easy => 4,
+            Difficulty::Medium => 8,
+            Difficulty::Hard => 14,
+            Difficulty::Perfect => 20,
+        }
+    }
+
+    /// Probability of playing another move than the best one found. The
+    /// AI still never plays a move its search, [`Difficulty::max_depth`]
+    /// plies deep, sees losing when it has a better one, nor misses an
+    /// immediate win. Losses further away may be played.
+    pub fn suboptimal_probability(self) -> f64 {
+        match self {
+            Difficulty::Beginner => 0.5,
+            Difficulty::Easy => 0.3,
+            Difficulty::Medium => 0.15,
+            Difficulty::Hard => 0.05,
+            Difficulty::Perfect => 0.0,
+        }
+    }
+
+    /// Amplitude of the noise added to static evaluations.
+    pub fn eval_noise(self) -> i16 {
+        match self {
+            Difficulty::Beginner => 80,
+            Difficulty::Easy => 40,
+            Difficulty::Medium => 15,
+            Difficulty::Hard => 5,
+            Difficulty::Perfect => 0,
+        }
+    }
+}
+
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownDifficulty(String);
+
+impl fmt::Display for UnknownDifficulty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "Unknown difficulty {:?}, expected one of: {}",
+            self.0,
+            Difficulty::ALL
+                .iter()
+                .map(|d| d.name())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    }
+}
+
+impl std::error::Error for UnknownDifficulty {}
+
+impl FromStr for Difficulty {
+    type Err = UnknownDifficulty;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Difficulty::ALL
+            .iter()
+            .copied()
+            .find(|d| d.name() == s)
+            .ok_or_else(|| UnknownDifficulty(s.to_owned()))
+    }
+}
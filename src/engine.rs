@@ -0,0 +1,429 @@
+//! Common interface of the game engines, so that the CLI and other
+//! front-ends can pick one by name.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::ai::{ColumnAnalysis, Score, AI};
+use crate::explain::Explanation;
+use crate::mcts::Mcts;
+use crate::packedboard::*;
+
+/// Limits of one search. Each engine honours those that make sense to it,
+/// and uses its own settings for the others.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Plies to look ahead.
+    pub depth: Option<u8>,
+    /// Playouts, for sampling engines.
+    pub iterations: Option<u64>,
+    pub time: Option<Duration>,
+}
+
+/// Stops the search an engine is running, from another thread. The engine
+/// then plays the best move it found so far.
+#[derive(Debug, Clone)]
+pub struct StopHandle(Arc<AtomicBool>);
+
+impl StopHandle {
+    pub(crate) fn new(flag: Arc<AtomicBool>) -> Self {
+        Self(flag)
+    }
+
+    /// Stops the search in progress, if any.
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// What an engine thinks of one move.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Assessment {
+    /// From a search, see [`AI::analyze`].
+    Searched(ColumnAnalysis),
+    /// From random games: the share of them won by the player to move
+    /// (draws counting for half), out of `playouts` games.
+    Sampled { win_rate: f64, playouts: u32 },
+}
+
+/// How an engine chose its move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveSource {
+    /// Looked up in an opening book, without searching.
+    Book,
+    /// A good move rather than the best one, played on purpose by a
+    /// weakened engine.
+    Deviation,
+    Search,
+}
+
+/// What an engine went through to choose a move. Sampling engines count
+/// their playouts as nodes, and have no score, depth nor transposition
+/// table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchInfo {
+    pub best_move: ColIdx,
+    pub source: MoveSource,
+    /// Value of the move for the player making it.
+    pub score: Option<Score>,
+    /// Share of the playouts through the move won by the player making it,
+    /// draws counting for half.
+    pub win_rate: Option<f64>,
+    /// Depth of the last complete iteration of the search.
+    pub depth: u8,
+    pub nodes: u64,
+    /// Positions found in the transposition table, and how many of them
+    /// needed no further search.
+    pub tt_hits: u64,
+    pub tt_cutoffs: u64,
+    pub elapsed: Duration,
+    /// The moves expected from `best_move` on, as far as the engine knows.
+    pub pv: Vec<ColIdx>,
+}
+
+impl SearchInfo {
+    pub fn nodes_per_second(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.nodes as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+impl fmt::Display for SearchInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let source = match self.source {
+            MoveSource::Book => "book",
+            MoveSource::Deviation => "deviation",
+            MoveSource::Search => "search",
+        };
+        write!(f, "{} {}:", source, moves_to_string(&[self.best_move]))?;
+        if let Some(score) = self.score {
+            write!(f, " score {},", score)?;
+        }
+        if let Some(win_rate) = self.win_rate {
+            write!(f, " win rate {:.0}%,", 100.0 * win_rate)?;
+        }
+        if self.depth > 0 {
+            write!(f, " depth {},", self.depth)?;
+        }
+        write!(
+            f,
+            " {} nodes in {:.3}s ({:.0} nodes/s),",
+            self.nodes,
+            self.elapsed.as_secs_f64(),
+            self.nodes_per_second()
+        )?;
+        if self.tt_hits > 0 {
+            write!(
+                f,
+                " {} TT hits ({} cutoffs),",
+                self.tt_hits, self.tt_cutoffs
+            )?;
+        }
+        write!(f, " expected line {}", moves_to_string(&self.pv))
+    }
+}
+
+/// Why an engine has no move to play in a position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveError {
+    /// Every column is full: the game ended in a draw.
+    BoardFull,
+    /// `winner` already has four tokens in a line.
+    AlreadyWon { winner: NonEmptySqrState },
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoveError::BoardFull => write!(f, "The board is full, there is no legal move"),
+            MoveError::AlreadyWon { winner } => {
+                write!(f, "The game is already won by {}", winner)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MoveError {}
+
+/// Whether the game goes on in `b`, so that there is a move to play.
+pub fn check_playable(b: &Board) -> Result<(), MoveError> {
+    if let Some(winner) = b.winner() {
+        Err(MoveError::AlreadyWon { winner })
+    } else if b.occupancy() == GRID_SIZE {
+        Err(MoveError::BoardFull)
+    } else {
+        Ok(())
+    }
+}
+
+/// The best column of `assessments`, as made by [`Engine::analyze`], and
+/// its assessment: the most central one of those as good, none if every
+/// column is full.
+pub fn best_assessment(assessments: &[Assessment; NCOL as usize]) -> Option<(ColIdx, Assessment)> {
+    let rank = |assessment: Assessment| match assessment {
+        Assessment::Searched(ColumnAnalysis::Full) => None,
+        Assessment::Searched(ColumnAnalysis::ImmediateLoss) => Some((0, 0.0)),
+        Assessment::Searched(ColumnAnalysis::Unsearched) => Some((1, 0.0)),
+        Assessment::Searched(ColumnAnalysis::Searched { score, .. }) => {
+            Some((2, score.get() as f64))
+        }
+        Assessment::Sampled { win_rate, .. } => Some((2, win_rate)),
+        Assessment::Searched(ColumnAnalysis::ImmediateWin) => Some((3, 0.0)),
+    };
+    let mut cols = ALL_COL_IDXS;
+    cols.sort_by_key(|coli| (coli.get() as i8 - (NCOL / 2) as i8).abs());
+    let mut best: Option<(ColIdx, Assessment, (u8, f64))> = None;
+    for coli in cols {
+        let assessment = assessments[coli.get() as usize];
+        if let Some(rank) = rank(assessment) {
+            if best.is_none_or(|(_, _, best_rank)| rank > best_rank) {
+                best = Some((coli, assessment, rank));
+            }
+        }
+    }
+    best.map(|(coli, assessment, _)| (coli, assessment))
+}
+
+pub trait Engine: Send {
+    fn name(&self) -> &'static str;
+
+    /// Forgets the current game and goes back to the empty board.
+    fn new_game(&mut self);
+
+    fn set_position(&mut self, b: &Board);
+
+    /// Searches the current position within `limits` and returns the move
+    /// to play, or why there is none.
+    fn go(&mut self, limits: &Limits) -> Result<SearchInfo, MoveError>;
+
+    fn stop_handle(&self) -> StopHandle;
+
+    /// Thinks about the current position while the opponent is choosing
+    /// their move in it, until stopped through [`Engine::stop_handle`], so
+    /// that the next `go` is quicker if they play one of the moves looked
+    /// at. A stop requested before pondering starts stops it right away,
+    /// and there is nothing to ponder once the game is over.
+    fn ponder(&mut self);
+
+    /// Assesses every column of the current position, full ones included,
+    /// at the full strength of the engine within `limits`: unlike `go`, a
+    /// weakened engine does not draw from its seed whether to deviate, so
+    /// that analysing leaves its next moves as they were.
+    fn analyze(&mut self, limits: &Limits) -> Result<[Assessment; NCOL as usize], MoveError>;
+
+    /// Why `col` is worth playing in the current position, as far as the
+    /// engine can tell within `limits`. Unlike the other searches, it does
+    /// not forget a stop requested during the last one: when the move was
+    /// played before the search was over, the explanation does not search
+    /// either, and only tells what the board shows.
+    fn explain(&mut self, col: ColIdx, limits: &Limits) -> Explanation;
+}
+
+pub const ENGINE_NAMES: [&str; 2] = ["negamax", "mcts"];
+
+/// The engine called `name` (see [`ENGINE_NAMES`]) with its default
+/// settings, `seed` seeding its random choices if it makes any.
+pub fn engine_by_name(name: &str, seed: u64) -> Option<Box<dyn Engine>> {
+    match name {
+        "negamax" => Some(Box::new(AI::new())),
+        "mcts" => Some(Box::new(Mcts::new(seed))),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::explain::Reason;
+    use std::time::Instant;
+
+    #[test]
+    fn engines_play_a_legal_game() {
+        let mut engines: Vec<_> = ENGINE_NAMES
+            .iter()
+            .map(|name| engine_by_name(name, 1).unwrap())
+            .collect();
+        let limits = Limits {
+            depth: Some(4),
+            iterations: Some(200),
+            time: None,
+        };
+        let mut b = Board::new();
+        for engine in &mut engines {
+            engine.new_game();
+        }
+        for turn in 0..GRID_SIZE as usize {
+            let engine = &mut engines[turn % 2];
+            engine.set_position(&b);
+            let coli = engine.go(&limits).unwrap().best_move;
+            if b.add_and_check(coli, b.to_play()).unwrap() {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn analyze_marks_full_columns() {
+        let b = Board::from_moves("111111").unwrap();
+        for name in &ENGINE_NAMES {
+            let mut engine = engine_by_name(name, 1).unwrap();
+            engine.set_position(&b);
+            let limits = Limits {
+                depth: Some(2),
+                iterations: Some(100),
+                time: None,
+            };
+            let columns = engine.analyze(&limits).unwrap();
+            assert_eq!(columns[0], Assessment::Searched(ColumnAnalysis::Full));
+            assert!(columns[1..]
+                .iter()
+                .all(|&c| c != Assessment::Searched(ColumnAnalysis::Full)));
+        }
+    }
+
+    #[test]
+    fn engines_explain_their_moves() {
+        let b = Board::from_moves("11223").unwrap();
+        for name in &ENGINE_NAMES {
+            let mut engine = engine_by_name(name, 1).unwrap();
+            engine.set_position(&b);
+            let limits = Limits {
+                depth: Some(4),
+                iterations: Some(200),
+                time: None,
+            };
+            let coli = engine.go(&limits).unwrap().best_move;
+            assert_eq!(coli, ALL_COL_IDXS[3], "{}", name);
+            let explanation = engine.explain(coli, &limits);
+            assert_eq!(
+                explanation.reasons[0],
+                Reason::BlocksImmediateWin { col: coli },
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn no_move_once_the_game_is_over() {
+        let drawn = Board::from_moves("676267454774755615735426464532211123113332").unwrap();
+        let won = Board::from_moves("1212121").unwrap();
+        assert_eq!(drawn.winner(), None);
+        assert_eq!(won.winner(), Some(NonEmptySqrState::Red));
+        for name in &ENGINE_NAMES {
+            let mut engine = engine_by_name(name, 1).unwrap();
+            let limits = Limits {
+                depth: Some(4),
+                iterations: Some(100),
+                time: None,
+            };
+            engine.set_position(&drawn);
+            assert_eq!(engine.go(&limits), Err(MoveError::BoardFull), "{}", name);
+            assert_eq!(
+                engine.analyze(&limits),
+                Err(MoveError::BoardFull),
+                "{}",
+                name
+            );
+            engine.ponder();
+            engine.set_position(&won);
+            let already_won = MoveError::AlreadyWon {
+                winner: NonEmptySqrState::Red,
+            };
+            assert_eq!(engine.go(&limits), Err(already_won), "{}", name);
+            assert_eq!(engine.analyze(&limits), Err(already_won), "{}", name);
+        }
+    }
+
+    #[test]
+    fn stop_interrupts_the_search() {
+        for name in &ENGINE_NAMES {
+            let mut engine = engine_by_name(name, 1).unwrap();
+            engine.set_position(&Board::new());
+            let handle = engine.stop_handle();
+            let start = Instant::now();
+            let stopper = std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(100));
+                handle.stop();
+            });
+            let limits = Limits {
+                depth: Some(42),
+                iterations: Some(u64::MAX),
+                time: None,
+            };
+            let coli = engine.go(&limits).unwrap().best_move;
+            stopper.join().unwrap();
+            // Not searching again for what the stopped search had no time for.
+            let explanation = engine.explain(coli, &limits);
+            assert!(start.elapsed() < Duration::from_secs(10), "{}", name);
+            assert!(!Board::new().col_is_full(coli));
+            assert_eq!(explanation.col, coli);
+        }
+    }
+
+    #[test]
+    fn stop_interrupts_pondering() {
+        for name in &ENGINE_NAMES {
+            let mut engine = engine_by_name(name, 1).unwrap();
+            engine.set_position(&Board::from_moves("4").unwrap());
+            let handle = engine.stop_handle();
+            let start = Instant::now();
+            let stopper = std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(100));
+                handle.stop();
+            });
+            engine.ponder();
+            stopper.join().unwrap();
+            assert!(start.elapsed() < Duration::from_secs(10), "{}", name);
+            // The next search is not stopped.
+            let limits = Limits {
+                depth: Some(4),
+                iterations: Some(100),
+                time: None,
+            };
+            engine.set_position(&Board::from_moves("44").unwrap());
+            let info = engine.go(&limits).unwrap();
+            assert!(info.nodes > 0, "{}", name);
+        }
+    }
+
+    #[test]
+    fn time_limit() {
+        for name in &ENGINE_NAMES {
+            let mut engine = engine_by_name(name, 1).unwrap();
+            engine.set_position(&Board::new());
+            let limits = Limits {
+                depth: Some(42),
+                iterations: Some(u64::MAX),
+                time: Some(Duration::from_millis(100)),
+            };
+            let start = Instant::now();
+            engine.go(&limits).unwrap();
+            assert!(start.elapsed() < Duration::from_secs(10), "{}", name);
+        }
+    }
+
+    #[test]
+    fn best_assessment_blocks_the_win() {
+        let b = Board::from_moves("11223").unwrap();
+        for name in &ENGINE_NAMES {
+            let mut engine = engine_by_name(name, 1).unwrap();
+            engine.set_position(&b);
+            let limits = Limits {
+                depth: Some(4),
+                iterations: Some(2000),
+                time: None,
+            };
+            let (coli, _) = best_assessment(&engine.analyze(&limits).unwrap()).unwrap();
+            assert_eq!(coli, ALL_COL_IDXS[3], "{}", name);
+        }
+        let full = [Assessment::Searched(ColumnAnalysis::Full); NCOL as usize];
+        assert_eq!(best_assessment(&full), None);
+    }
+}
@@ -0,0 +1,241 @@
+//! Static evaluation of positions, used by the search once it runs out of
+//! depth.
+
+use crate::packedboard::*;
+use crate::rng::mix;
+
+/// Evaluations are clamped to `-MAX_EVAL..=MAX_EVAL`, which keeps them apart
+/// from the scores of proven wins and losses.
+pub const MAX_EVAL: i16 = 999;
+
+const fn column_mask(rows: u64) -> u64 {
+    let mut res = 0;
+    let mut coli = 0;
+    while coli < NCOL {
+        res |= rows << (coli * BITBOARD_HEIGHT);
+        coli += 1;
+    }
+    res
+}
+
+/// Every cell of the board in the [`Board::bitboard`] layout.
+pub const BOARD_MASK: u64 = column_mask((1 << NROW) - 1);
+/// The bottom cell of each column.
+pub const BOTTOM_MASK: u64 = column_mask(1);
+/// Rows 1, 3 and 5 counting from the bottom, where threats favour Red.
+pub const ODD_ROWS_MASK: u64 = column_mask(0b010101);
+/// Rows 2, 4 and 6 counting from the bottom, where threats favour Yellow.
+pub const EVEN_ROWS_MASK: u64 = column_mask(0b101010);
+
+/// Number of winning lines going through each cell, indexed by column then
+/// row.
+const LINES_THROUGH: [[i16; NROW as usize]; NCOL as usize] = [
+    [3, 4, 5, 5, 4, 3],
+    [4, 6, 8, 8, 6, 4],
+    [5, 8, 11, 11, 8, 5],
+    [7, 10, 13, 13, 10, 7],
+    [5, 8, 11, 11, 8, 5],
+    [4, 6, 8, 8, 6, 4],
+    [3, 4, 5, 5, 4, 3],
+];
+
+/// [`LINES_THROUGH`], indexed by bit in the [`Board::bitboard`] layout.
+const CELL_WEIGHTS: [i16; 64] = {
+    let mut res = [0; 64];
+    let mut coli = 0;
+    while coli < NCOL {
+        let mut rowi = 0;
+        while rowi < NROW {
+            res[(coli * BITBOARD_HEIGHT + rowi) as usize] =
+                LINES_THROUGH[coli as usize][rowi as usize];
+            rowi += 1;
+        }
+        coli += 1;
+    }
+    res
+};
+
+/// Empty cells that would complete a line of four for the owner of `own`.
+pub fn winning_cells(own: u64, occupied: u64) -> u64 {
+    let h = BITBOARD_HEIGHT;
+    // Vertical: only the cell on top of three tokens.
+    let mut res = (own << 1) & (own << 2) & (own << 3);
+    // Horizontal, then both diagonals.
+    for &step in &[h, h - 1, h + 1] {
+        let p = (own << step) & (own << (2 * step));
+        res |= p & (own << (3 * step));
+        res |= p & (own >> step);
+        let p = (own >> step) & (own >> (2 * step));
+        res |= p & (own << step);
+        res |= p & (own >> (3 * step));
+    }
+    res & (BOARD_MASK ^ occupied)
+}
+
+/// Cells where the next token of each column would land.
+pub fn playable_cells(occupied: u64) -> u64 {
+    (occupied + BOTTOM_MASK) & BOARD_MASK
+}
+
+/// A static evaluation function for the search horizon.
+pub trait Evaluate: Send + Sync {
+    /// Scores `b` from the point of view of `color`, the player about to
+    /// move. Positive values are good for `color` and must stay within
+    /// `-MAX_EVAL..=MAX_EVAL`.
+    fn evaluate(&self, b: &Board, color: NonEmptySqrState) -> i16;
+}
+
+/// Knows nothing about the position: every unsolved position is worth 0.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Neutral;
+
+impl Evaluate for Neutral {
+    fn evaluate(&self, _b: &Board, _color: NonEmptySqrState) -> i16 {
+        0
+    }
+}
+
+/// Weighted sum of threats, threat parity and central tokens.
+#[derive(Debug, Clone, Copy)]
+pub struct Heuristic {
+    /// Per cell that would complete a line.
+    pub threat: i16,
+    /// Extra, per threat on a row of the favourable parity for its owner.
+    pub parity_threat: i16,
+    /// Extra, per threat that can be played right away.
+    pub immediate_threat: i16,
+    /// Multiplies the [number of lines](LINES_THROUGH) through each token.
+    pub center: i16,
+}
+
+impl Default for Heuristic {
+    fn default() -> Self {
+        Self {
+            threat: 8,
+            parity_threat: 12,
+            immediate_threat: 40,
+            center: 1,
+        }
+    }
+}
+
+impl Heuristic {
+    fn side(&self, own: u64, occupied: u64, color: NonEmptySqrState) -> i32 {
+        let threats = winning_cells(own, occupied);
+        let good_parity = match color {
+            NonEmptySqrState::Red => ODD_ROWS_MASK,
+            NonEmptySqrState::Yellow => EVEN_ROWS_MASK,
+        };
+        let mut centrality = 0;
+        let mut tokens = own;
+        while tokens != 0 {
+            centrality += CELL_WEIGHTS[tokens.trailing_zeros() as usize] as i32;
+            tokens &= tokens - 1;
+        }
+        self.threat as i32 * threats.count_ones() as i32
+            + self.parity_threat as i32 * (threats & good_parity).count_ones() as i32
+            + self.immediate_threat as i32
+                * (threats & playable_cells(occupied)).count_ones() as i32
+            + self.center as i32 * centrality
+    }
+}
+
+impl Evaluate for Heuristic {
+    fn evaluate(&self, b: &Board, color: NonEmptySqrState) -> i16 {
+        let occupied = b.occupied_bitboard();
+        let own = self.side(b.bitboard(color), occupied, color);
+        let other = self.side(b.bitboard(color.other()), occupied, color.other());
+        (own - other).clamp(-MAX_EVAL as i32, MAX_EVAL as i32) as i16
+    }
+}
+
+/// Adds to `inner` a pseudo-random noise in `-amplitude..=amplitude`. The
+/// noise only depends on the position and the seed, so that the search sees
+/// a consistent evaluation and games can be replayed.
+pub struct Noisy {
+    pub inner: Box<dyn Evaluate>,
+    pub amplitude: i16,
+    pub seed: u64,
+}
+
+impl Evaluate for Noisy {
+    fn evaluate(&self, b: &Board, color: NonEmptySqrState) -> i16 {
+        let eval = self.inner.evaluate(b, color);
+        if self.amplitude == 0 {
+            return eval;
+        }
+        let span = 2 * self.amplitude as u64 + 1;
+        let noise = (mix(b.key() ^ self.seed) % span) as i16 - self.amplitude;
+        // The noise is for the position, whoever evaluates it.
+        let noise = match color {
+            NonEmptySqrState::Red => noise,
+            NonEmptySqrState::Yellow => -noise,
+        };
+        (eval + noise).clamp(-MAX_EVAL, MAX_EVAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(moves: &[u8]) -> Board {
+        let mut b = Board::new();
+        let mut color = NonEmptySqrState::Red;
+        for &m in moves {
+            b.add_to_col(ALL_COL_IDXS[m as usize], color).unwrap();
+            color = color.other();
+        }
+        b
+    }
+
+    #[test]
+    fn bitboards_partition_occupied() {
+        let b = play(&[3, 3, 2, 4, 4, 6, 0]);
+        let red = b.bitboard(NonEmptySqrState::Red);
+        let yellow = b.bitboard(NonEmptySqrState::Yellow);
+        assert_eq!(red & yellow, 0);
+        assert_eq!(red | yellow, b.occupied_bitboard());
+        assert_eq!(red.count_ones(), 4);
+        assert_eq!(yellow.count_ones(), 3);
+    }
+
+    #[test]
+    fn finds_winning_cells() {
+        // Red has 3 in a row on the bottom row, in columns 2 to 4.
+        let b = play(&[1, 1, 2, 2, 3]);
+        let red = b.bitboard(NonEmptySqrState::Red);
+        let cells = winning_cells(red, b.occupied_bitboard());
+        let expected = 1 | (1 << (4 * BITBOARD_HEIGHT));
+        assert_eq!(cells, expected);
+        assert_eq!(cells & playable_cells(b.occupied_bitboard()), expected);
+    }
+
+    #[test]
+    fn heuristic_is_antisymmetric_and_bounded() {
+        let h = Heuristic::default();
+        let b = play(&[3, 3, 2, 4, 4, 6, 0, 2]);
+        let red = h.evaluate(&b, NonEmptySqrState::Red);
+        assert_eq!(red, -h.evaluate(&b, NonEmptySqrState::Yellow));
+        assert!(red.abs() <= MAX_EVAL);
+        assert!(h.evaluate(&play(&[1, 1, 2, 2, 3]), NonEmptySqrState::Red) > 0);
+    }
+
+    #[test]
+    fn noise_is_bounded_and_reproducible() {
+        let noisy = |seed| Noisy {
+            inner: Box::new(Neutral),
+            amplitude: 10,
+            seed,
+        };
+        let b = play(&[3, 3, 2]);
+        let eval = noisy(1).evaluate(&b, NonEmptySqrState::Red);
+        assert!(eval.abs() <= 10);
+        assert_eq!(eval, noisy(1).evaluate(&b, NonEmptySqrState::Red));
+        assert_eq!(eval, -noisy(1).evaluate(&b, NonEmptySqrState::Yellow));
+        let evals: Vec<_> = (0..10)
+            .map(|seed| noisy(seed).evaluate(&b, NonEmptySqrState::Red))
+            .collect();
+        assert!(evals.iter().any(|&e| e != eval));
+    }
+}
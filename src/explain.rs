@@ -0,0 +1,292 @@
+//! Reasons for a move in plain language, derived from the position and from
+//! what the search found about every column.
+
+use std::fmt;
+
+use crate::ai::{Analysis, Bound, ColumnAnalysis, Score};
+use crate::eval::{playable_cells, winning_cells};
+use crate::packedboard::*;
+
+/// Column the engines try first among moves of the same value.
+const CENTER: u8 = NCOL / 2;
+
+/// One reason for a move, told by the engine to its opponent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reason {
+    /// The column is full: the move cannot be played.
+    ColumnFull,
+    /// The move wins right away.
+    WinsNow,
+    /// The opponent would have won right away in `col`, the column played.
+    BlocksImmediateWin { col: ColIdx },
+    /// The move leaves `count` more empty cells, at least two, where a token
+    /// of the player would win.
+    CreatesThreats { count: u32 },
+    /// The move wins with best play, with the `turn`th token of the player,
+    /// counted as in the display of a [`Score`].
+    ForcedWin { turn: u8 },
+    /// Every other move loses with best play, this one does not.
+    OnlyNonLosingMove,
+    /// Every move draws with best play, `col` being the one played.
+    AllMovesDraw { col: ColIdx },
+    /// Every move loses with best play, this one the latest, with the
+    /// `turn`th token of the opponent.
+    DelaysLoss { turn: u8 },
+    /// The move draws with best play, and no move wins.
+    ForcedDraw,
+    /// A better move was found, but the engine plays below its best.
+    Suboptimal,
+    /// The search found nothing certain, and the move has the best
+    /// evaluation.
+    BestEvaluation,
+    /// The share of the random playouts through the move won by the player,
+    /// draws counting for half.
+    WinRate { win_rate: f64 },
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Reason::ColumnFull => write!(f, "nothing, the column is full"),
+            Reason::WinsNow => write!(f, "wins right away"),
+            Reason::BlocksImmediateWin { col } => write!(
+                f,
+                "blocks your immediate win in column {}",
+                moves_to_string(&[col])
+            ),
+            Reason::CreatesThreats { count: 2 } => write!(f, "creates two threats"),
+            Reason::CreatesThreats { count: 3 } => write!(f, "creates three threats"),
+            Reason::CreatesThreats { count } => write!(f, "creates {} threats", count),
+            Reason::ForcedWin { turn } => write!(f, "I win at turn n°{}", turn),
+            Reason::OnlyNonLosingMove => write!(f, "only non-losing move"),
+            Reason::AllMovesDraw { col } if col.get() == CENTER => {
+                write!(f, "all moves draw; chose the center")
+            }
+            Reason::AllMovesDraw { col } => write!(
+                f,
+                "all moves draw; chose column {}",
+                moves_to_string(&[col])
+            ),
+            Reason::DelaysLoss { turn } => {
+                write!(f, "every move loses; you win at turn n°{} at best", turn)
+            }
+            Reason::ForcedDraw => write!(f, "nothing wins; best play draws"),
+            Reason::Suboptimal => write!(f, "not my best move, to give you a chance"),
+            Reason::BestEvaluation => write!(f, "nothing is forced yet; best evaluation"),
+            Reason::WinRate { win_rate } => {
+                write!(f, "wins {:.0}% of its random playouts", 100.0 * win_rate)
+            }
+        }
+    }
+}
+
+/// Why a move was played, the most important reason first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation {
+    pub col: ColIdx,
+    pub reasons: Vec<Reason>,
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reasons: Vec<String> = self.reasons.iter().map(Reason::to_string).collect();
+        write!(
+            f,
+            "Move {} chosen because: {}",
+            moves_to_string(&[self.col]),
+            reasons.join(", ")
+        )
+    }
+}
+
+impl Explanation {
+    /// The reasons for playing `col` in `b` that can be seen on the board
+    /// alone: an immediate win, a blocked win and new threats, or else that
+    /// `col` is full.
+    pub fn tactical(b: &Board, col: ColIdx) -> Self {
+        let color = b.to_play();
+        let mut child = b.clone();
+        let mut reasons = Vec::new();
+        match child.add_and_check(col, color) {
+            Ok(false) => {}
+            Ok(true) => {
+                reasons.push(Reason::WinsNow);
+                return Self { col, reasons };
+            }
+            Err(_) => {
+                reasons.push(Reason::ColumnFull);
+                return Self { col, reasons };
+            }
+        }
+        let occupied = b.occupied_bitboard();
+        let played = child.occupied_bitboard() ^ occupied;
+        let their_wins = winning_cells(b.bitboard(color.other()), occupied);
+        if their_wins & playable_cells(occupied) & played != 0 {
+            reasons.push(Reason::BlocksImmediateWin { col });
+        }
+        let threats = |b: &Board| winning_cells(b.bitboard(color), b.occupied_bitboard());
+        let count = (threats(&child) & !threats(b)).count_ones();
+        if count >= 2 {
+            reasons.push(Reason::CreatesThreats { count });
+        }
+        Self { col, reasons }
+    }
+
+    /// The reasons for playing `col` in `b`, where `analysis` is what the
+    /// search found about `b`.
+    pub fn new(b: &Board, col: ColIdx, analysis: &Analysis) -> Self {
+        let mut res = Self::tactical(b, col);
+        if res.reasons == [Reason::WinsNow] || res.reasons == [Reason::ColumnFull] {
+            return res;
+        }
+        let column = |col: ColIdx| analysis.columns[col.get() as usize];
+        let score = match column(col) {
+            ColumnAnalysis::Searched { score, .. } => score,
+            ColumnAnalysis::ImmediateLoss
+                if analysis.best_move.map(column) == Some(ColumnAnalysis::ImmediateLoss) =>
+            {
+                analysis.score
+            }
+            ColumnAnalysis::ImmediateLoss => {
+                res.reasons.push(Reason::Suboptimal);
+                return res;
+            }
+            _ => {
+                if res.reasons.is_empty() {
+                    res.reasons.push(Reason::BestEvaluation);
+                }
+                return res;
+            }
+        };
+        // The other columns.
+        let legal = || {
+            ALL_COL_IDXS
+                .iter()
+                .zip(&analysis.columns)
+                .filter(|&(&coli, column)| coli != col && *column != ColumnAnalysis::Full)
+                .map(|(_, column)| column)
+        };
+        let loses = |column: &ColumnAnalysis| match *column {
+            ColumnAnalysis::ImmediateLoss => true,
+            ColumnAnalysis::Searched { score, bound } => {
+                score.is_proven() && score.get() < 0 && bound != Bound::Lower
+            }
+            _ => false,
+        };
+        let solved = analysis.depth >= GRID_SIZE - b.occupancy();
+        let draws = |column: &ColumnAnalysis| {
+            *column
+                == ColumnAnalysis::Searched {
+                    score: Score::new(0),
+                    bound: Bound::Exact,
+                }
+        };
+        if score < analysis.score {
+            res.reasons.push(Reason::Suboptimal);
+        } else if let Some(turn) = score.winner_tokens() {
+            if score.get() > 0 {
+                res.reasons.insert(0, Reason::ForcedWin { turn });
+            } else {
+                res.reasons.push(Reason::DelaysLoss { turn });
+            }
+        } else if legal().count() > 0 && legal().all(loses) {
+            res.reasons.push(Reason::OnlyNonLosingMove);
+        } else if solved && score.get() == 0 && legal().all(draws) {
+            res.reasons.push(Reason::AllMovesDraw { col });
+        } else if solved && score.get() == 0 {
+            res.reasons.push(Reason::ForcedDraw);
+        } else if res.reasons.is_empty() {
+            res.reasons.push(Reason::BestEvaluation);
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::AI;
+
+    fn explain(moves: &str) -> Explanation {
+        let b = Board::from_moves(moves).unwrap();
+        let mut ai = AI::new();
+        let col = ai.make_a_move(&b).unwrap();
+        ai.explain(&b, col)
+    }
+
+    #[test]
+    fn tactics() {
+        let b = Board::from_moves("112233").unwrap();
+        let col = ALL_COL_IDXS[3];
+        assert_eq!(Explanation::tactical(&b, col).reasons, [Reason::WinsNow]);
+        let b = Board::from_moves("11223").unwrap();
+        assert_eq!(
+            Explanation::tactical(&b, col).to_string(),
+            "Move 4 chosen because: blocks your immediate win in column 4"
+        );
+        assert!(Explanation::tactical(&b, ALL_COL_IDXS[4])
+            .reasons
+            .is_empty());
+        let b = Board::from_moves("111111").unwrap();
+        assert_eq!(
+            Explanation::tactical(&b, ALL_COL_IDXS[0]).to_string(),
+            "Move 1 chosen because: nothing, the column is full"
+        );
+        let b = Board::from_moves("231634161247672231544674712724").unwrap();
+        assert_eq!(
+            AI::new().explain(&b, ALL_COL_IDXS[1]).reasons,
+            [Reason::ColumnFull]
+        );
+    }
+
+    #[test]
+    fn reasons_from_the_analysis() {
+        assert_eq!(
+            explain("44255667653443776754475652712633").reasons,
+            [
+                Reason::BlocksImmediateWin {
+                    col: ALL_COL_IDXS[0]
+                },
+                Reason::OnlyNonLosingMove
+            ]
+        );
+        assert_eq!(
+            explain("17516134472776667276223256321333").to_string(),
+            "Move 5 chosen because: I win at turn n°18, creates two threats"
+        );
+        assert_eq!(
+            explain("67766221263764463241752275531715").reasons,
+            [Reason::AllMovesDraw {
+                col: ALL_COL_IDXS[2]
+            }]
+        );
+        assert_eq!(
+            explain("72372676465254663776332217253355").reasons,
+            [Reason::ForcedDraw]
+        );
+        assert_eq!(
+            explain("231634161247672231544674712724").reasons,
+            [Reason::DelaysLoss { turn: 21 }]
+        );
+    }
+
+    #[test]
+    fn worse_moves_are_told_apart() {
+        let b = Board::from_moves("2252576253462244111563365343671351").unwrap();
+        let mut ai = AI::new();
+        assert_eq!(
+            ai.explain(&b, ALL_COL_IDXS[0]).reasons,
+            [Reason::Suboptimal]
+        );
+        assert_eq!(
+            ai.explain(&b, ALL_COL_IDXS[3]).reasons,
+            [
+                Reason::BlocksImmediateWin {
+                    col: ALL_COL_IDXS[3]
+                },
+                Reason::Suboptimal
+            ]
+        );
+        assert_eq!(ai.explain(&b, ALL_COL_IDXS[5]).reasons, [Reason::WinsNow]);
+    }
+}
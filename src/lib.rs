@@ -0,0 +1,15 @@
+pub mod ai;
+pub mod arena;
+pub mod bench;
+pub mod board;
+pub mod book;
+pub mod difficulty;
+pub mod engine;
+pub mod eval;
+pub mod explain;
+pub mod mcts;
+pub mod nn;
+pub mod packedboard;
+pub mod rng;
+mod solved;
+pub mod tablebase;
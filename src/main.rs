@@ -1,18 +1,122 @@
-mod ai;
-use ai::AI;
-mod board;
-mod packedboard;
-use packedboard::*;
-use io::BufRead;
+use four_in_a_rust::ai::{ColumnAnalysis, AI};
+use four_in_a_rust::arena::{openings, run_match, Player, Sprt, SprtVerdict, OPENING_PLIES};
+use four_in_a_rust::bench::{read_positions, run_bucket};
+use four_in_a_rust::book::Book;
+use four_in_a_rust::difficulty::Difficulty;
+use four_in_a_rust::engine::{
+    best_assessment, engine_by_name, Assessment, Engine, Limits, MoveError, ENGINE_NAMES,
+};
+use four_in_a_rust::nn::{self, Network, Training, DEFAULT_HIDDEN};
+use four_in_a_rust::packedboard::*;
+use four_in_a_rust::tablebase::{self, Tablebase};
 use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
 
-fn main() -> anyhow::Result<()> {
+const USAGE: &str = "usage:
+    four_in_a_rust [--engine ENGINE] [--book FILE] [--tablebase FILE] [--level LEVEL]
+                   [--network FILE] [--threads N] [--cache FILE] [--seed N] [--ponder]
+                   [--count-hints]
+                                           play against the AI, ENGINE being negamax
+                                           (the default) or mcts, LEVEL being one of
+                                           beginner, easy, medium, hard or perfect;
+                                           --network evaluates with a trained network;
+                                           --threads searches with N threads;
+                                           --cache starts from the positions solved
+                                           into FILE by earlier runs, and saves them
+                                           there at the end of the game;
+                                           with --ponder, the AI thinks on your time;
+                                           Ctrl-C makes it play its best move so far;
+                                           type hint for the AI's advice on your move,
+                                           counted at the end with --count-hints
+    four_in_a_rust book [--cache CACHE] FILE PLY [MOVES]
+                                           solve every position up to PLY tokens
+                                           (from the one reached by MOVES) into FILE;
+                                           with --cache, start from the positions
+                                           solved into CACHE by earlier runs, and
+                                           save them there for the next ones
+    four_in_a_rust tablebase FILE EMPTY MOVES
+                                           compute the endgame of the position reached
+                                           by MOVES into FILE: every position after it
+                                           with at most EMPTY empty cells; MOVES must
+                                           leave few empty cells, all the positions
+                                           after them being enumerated
+    four_in_a_rust match PLAYER PLAYER [PAIRS]
+                                           play up to PAIRS pairs of games (default 100)
+                                           between two engines, PLAYER being written
+                                           ENGINE[:KEY=VALUE,...], e.g. negamax:level=hard
+                                           or mcts:iterations=5000,time=100
+    four_in_a_rust bench [--threads N] FILE...
+                                           solve the positions of each FILE, written
+                                           \"MOVES SCORE\" one per line, and check
+                                           their scores; with N threads, solve them
+                                           with one thread too and tell the speedup
+    four_in_a_rust train FILE SOURCE COUNT [EPOCHS]
+                                           train a neural network evaluation into FILE
+                                           on COUNT samples, SOURCE being selfplay (COUNT
+                                           games) or solver (COUNT solved positions)";
+
+/// Depth of the search in the self-play games of the training command.
+const SELF_PLAY_DEPTH: u8 = 6;
+/// Number of empty cells of the positions solved for the training command.
+const SOLVED_EMPTY: u8 = 16;
+
+/// How the game against the AI goes, besides the engine.
+#[derive(Debug, Clone, Copy, Default)]
+struct GameOptions {
+    /// The AI thinks on the player's time.
+    ponder: bool,
+    /// The number of hints used is told at the end of the game.
+    count_hints: bool,
+}
+
+/// The value of the move of a hint, for the player asking for it.
+fn hint_evaluation(assessment: Assessment) -> String {
+    match assessment {
+        Assessment::Searched(ColumnAnalysis::ImmediateWin) => "you win right away".to_owned(),
+        Assessment::Searched(ColumnAnalysis::Searched { score, .. }) if score.is_proven() => {
+            format!(
+                "{} win at turn n°{}",
+                if score.get() > 0 { "you" } else { "I" },
+                score.winner_tokens().unwrap()
+            )
+        }
+        Assessment::Searched(ColumnAnalysis::Searched { score, .. }) => {
+            format!("evaluation: {}", score.get())
+        }
+        Assessment::Sampled { win_rate, .. } => format!("win rate {:.0}%", 100.0 * win_rate),
+        Assessment::Searched(_) => "no evaluation".to_owned(),
+    }
+}
+
+fn game_over(b: &Board, message: &str, hints: u32, options: GameOptions) {
+    print!("{}", b);
+    println!("{}", message);
+    if options.count_hints {
+        println!("Hints used: {}", hints);
+    }
+}
+
+fn play(ai: &mut dyn Engine, options: GameOptions) -> anyhow::Result<()> {
     let mut b = Board::new();
+    let mut hints = 0;
+    ai.new_game();
+    let thinking = Arc::new(AtomicBool::new(false));
+    {
+        let (thinking, stop) = (thinking.clone(), ai.stop_handle());
+        ctrlc::set_handler(move || {
+            if thinking.load(Ordering::Relaxed) {
+                stop.stop();
+            } else {
+                std::process::exit(130);
+            }
+        })?;
+    }
     // let file = std::fs::File::open("input.txt")?;
     // let mut bufreader = std::io::BufReader::new(file);
     let bufreader = std::io::stdin();
-    let mut ai = AI::new();
-    dbg!(std::mem::size_of::<Option<ColIdx>>());
     loop {
         print!("{}", b);
         //    print!("{:?}",b);
@@ -22,27 +126,357 @@ fn main() -> anyhow::Result<()> {
         );
         io::stdout().flush()?;
         let mut input = String::new();
-        bufreader.read_line(&mut input)?;
+        let read = if options.ponder {
+            ai.set_position(&b);
+            let stop = ai.stop_handle();
+            let ai = &mut *ai;
+            thread::scope(|scope| {
+                let pondering = scope.spawn(move || ai.ponder());
+                let read = bufreader.read_line(&mut input);
+                stop.stop();
+                pondering.join().unwrap();
+                read
+            })?
+        } else {
+            bufreader.read_line(&mut input)?
+        };
+        if read == 0 {
+            // End of the input: the player left.
+            println!();
+            return Ok(());
+        }
+        if input.trim() == "hint" {
+            ai.set_position(&b);
+            thinking.store(true, Ordering::Relaxed);
+            // Not `go`, which would draw the deviations of a weakened AI
+            // from its seed.
+            let assessments = ai.analyze(&Limits::default())?;
+            thinking.store(false, Ordering::Relaxed);
+            hints += 1;
+            // The game goes on, so some column is not full.
+            let (col, assessment) = best_assessment(&assessments).unwrap();
+            println!(
+                "Hint: play {} ({})",
+                moves_to_string(&[col]),
+                hint_evaluation(assessment)
+            );
+            continue;
+        }
         match input.trim().parse::<u8>() {
-            Err(_) => {
+            Err(_) | Ok(0) => {
                 println!("Parse error :(");
                 // anyhow::bail!("Parse error");
                 continue;
             }
             Ok(n) => {
-                let colidx = Board::check_col_idx(n-1)?;
-                if b.add_and_check(colidx, NonEmptySqrState::Red)? {
-                    print!("{}", b);
-                    println!("You won !!!!");
-                    return Ok(());
+                let played = Board::check_col_idx(n - 1)
+                    .and_then(|colidx| b.add_and_check(colidx, NonEmptySqrState::Red));
+                match played {
+                    Err(e) => {
+                        println!("{}", e);
+                        continue;
+                    }
+                    Ok(true) => {
+                        game_over(&b, "You won !!!!", hints, options);
+                        return Ok(());
+                    }
+                    Ok(false) => {}
                 }
             }
         };
-        let aimove = ai.make_a_move(&b);
+        ai.set_position(&b);
+        thinking.store(true, Ordering::Relaxed);
+        let limits = Limits::default();
+        let info = match ai.go(&limits) {
+            Ok(info) => info,
+            Err(MoveError::BoardFull) => {
+                thinking.store(false, Ordering::Relaxed);
+                game_over(&b, "Draw: the board is full.", hints, options);
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        // Mostly answered by the cache, and not searching at all after
+        // Ctrl-C.
+        let explanation = ai.explain(info.best_move, &limits);
+        thinking.store(false, Ordering::Relaxed);
+        println!("{}", info);
+        println!("{}", explanation);
+        let aimove = info.best_move;
         if b.add_and_check(aimove, NonEmptySqrState::Yellow)? {
-            print!("{}", b);
-            println!("You lost.");
+            game_over(&b, "You lost.", hints, options);
+            return Ok(());
+        }
+        if b.occupancy() == GRID_SIZE {
+            game_over(&b, "Draw: the board is full.", hints, options);
             return Ok(());
         }
     }
 }
+
+fn make_book(
+    path: &str,
+    max_ply: &str,
+    moves: Option<&str>,
+    cache: Option<&str>,
+) -> anyhow::Result<()> {
+    let root = Board::from_moves(moves.unwrap_or(""))?;
+    let max_ply = max_ply.parse()?;
+    let mut ai = AI::new().with_threads(std::thread::available_parallelism()?.get());
+    // Missing the first time.
+    if let Some(cache) = cache.filter(|cache| Path::new(cache).exists()) {
+        println!(
+            "Read {} solved positions from {}",
+            ai.load_cache(cache)?,
+            cache
+        );
+    }
+    let book = Book::generate(&root, max_ply, &mut ai, |ply, positions| {
+        println!("Solving {} positions with {} tokens", positions, ply)
+    });
+    book.save(path)?;
+    println!("Wrote {} positions to {}", book.len(), path);
+    if let Some(cache) = cache {
+        ai.save_cache(cache)?;
+    }
+    Ok(())
+}
+
+fn make_tablebase(path: &str, max_empty: &str, moves: &str) -> anyhow::Result<()> {
+    let root = Board::from_moves(moves)?;
+    let tablebase = Tablebase::generate(
+        &root,
+        max_empty.parse()?,
+        tablebase::MAX_POSITIONS,
+        |empty, positions| {
+            println!(
+                "Computing {} positions with {} empty cells",
+                positions, empty
+            )
+        },
+    )?;
+    tablebase.save(path)?;
+    println!("Wrote {} positions to {}", tablebase.len(), path);
+    Ok(())
+}
+
+fn play_match(first: &str, second: &str, pairs: Option<&str>) -> anyhow::Result<()> {
+    let first: Player = first.parse()?;
+    let second: Player = second.parse()?;
+    let pairs = pairs.map_or(Ok(100), str::parse)?;
+    let sprt = Sprt::default();
+    let result = run_match(
+        &first,
+        &second,
+        &openings(OPENING_PLIES, 0),
+        pairs,
+        &sprt,
+        |result| {
+            println!(
+                "{} games: {}, LLR {:.2}",
+                result.games(),
+                result,
+                result.llr(&sprt)
+            )
+        },
+    );
+    let (lower, upper) = sprt.bounds();
+    println!(
+        "{} vs {}: {}, LLR {:.2} ({:.2}, {:.2})",
+        first,
+        second,
+        result,
+        result.llr(&sprt),
+        lower,
+        upper
+    );
+    match result.sprt(&sprt) {
+        SprtVerdict::AcceptH1 => println!("H1 accepted: {} is stronger", first),
+        SprtVerdict::AcceptH0 => println!("H0 accepted: {} is not stronger", first),
+        SprtVerdict::Continue => println!("No verdict yet, more games are needed"),
+    }
+    Ok(())
+}
+
+fn bench(paths: &[&str], threads: Option<&str>) -> anyhow::Result<()> {
+    let threads = threads.map_or(Ok(1), str::parse)?;
+    let mut failures = 0;
+    for path in paths {
+        let positions = read_positions(io::BufReader::new(std::fs::File::open(path)?))?;
+        let name = Path::new(path)
+            .file_stem()
+            .map_or(*path, |stem| stem.to_str().unwrap_or(path));
+        let single = run_bucket(name, &positions, 1);
+        let mut reports = vec![single];
+        if threads > 1 {
+            reports.push(run_bucket(name, &positions, threads));
+        }
+        for report in &reports {
+            println!("{}", report);
+            for failure in &report.failures {
+                println!(
+                    "    {}: expected {}, got {}",
+                    failure.moves, failure.expected, failure.got
+                );
+            }
+        }
+        // Both runs solve the same positions.
+        failures += reports.iter().map(|r| r.failures.len()).max().unwrap_or(0);
+        if let [single, parallel] = &reports[..] {
+            println!(
+                "{}: speedup {:.2} with {} threads",
+                name,
+                parallel.speedup_over(single),
+                threads
+            );
+        }
+    }
+    if failures > 0 {
+        anyhow::bail!("{} positions solved to the wrong score", failures);
+    }
+    Ok(())
+}
+
+fn train(path: &str, source: &str, count: &str, epochs: Option<&str>) -> anyhow::Result<()> {
+    let count = count.parse()?;
+    let samples = match source {
+        "selfplay" => nn::self_play_samples(count, SELF_PLAY_DEPTH, 0),
+        "solver" => nn::solved_samples(count, SOLVED_EMPTY, 0),
+        _ => anyhow::bail!("unknown training source: {}\n{}", source, USAGE),
+    };
+    let mut training = Training::default();
+    if let Some(epochs) = epochs {
+        training.epochs = epochs.parse()?;
+    }
+    let mut network = Network::new(DEFAULT_HIDDEN, 0);
+    println!(
+        "Training on {} positions, loss {:.4}",
+        samples.len(),
+        network.loss(&samples)
+    );
+    network.train(&samples, &training, |epoch, loss| {
+        println!("Epoch {}: loss {:.4}", epoch, loss)
+    });
+    network.save(path)?;
+    println!("Wrote the network to {}", path);
+    Ok(())
+}
+
+/// The engine to play against.
+enum Opponent<'a> {
+    /// The negamax AI, and the file its cache is saved to after the game.
+    Negamax(Box<AI>, Option<&'a str>),
+    Other(Box<dyn Engine>),
+}
+
+/// The engine to play against, and how the game goes.
+fn play_options<'a>(mut args: &[&'a str]) -> anyhow::Result<(Opponent<'a>, GameOptions)> {
+    let mut engine = ENGINE_NAMES[0];
+    let mut book = None;
+    let mut tablebase = None;
+    let mut level = None;
+    let mut network = None;
+    let mut threads = None;
+    let mut cache = None;
+    let mut seed = 0;
+    let mut options = GameOptions::default();
+    loop {
+        args = match args {
+            [] => break,
+            ["--ponder", rest @ ..] => {
+                options.ponder = true;
+                rest
+            }
+            ["--count-hints", rest @ ..] => {
+                options.count_hints = true;
+                rest
+            }
+            [option, value, rest @ ..] => {
+                match *option {
+                    "--engine" => engine = value,
+                    "--book" => book = Some(Book::load(value)?),
+                    "--tablebase" => tablebase = Some(Tablebase::load(value)?),
+                    "--level" => level = Some(value.parse()?),
+                    "--network" => network = Some(Network::load(value)?),
+                    "--threads" => threads = Some(value.parse()?),
+                    "--cache" => cache = Some(*value),
+                    "--seed" => seed = value.parse()?,
+                    _ => anyhow::bail!("{}", USAGE),
+                }
+                rest
+            }
+            _ => anyhow::bail!("{}", USAGE),
+        };
+    }
+    if engine != "negamax" {
+        if book.is_some()
+            || tablebase.is_some()
+            || level.is_some()
+            || network.is_some()
+            || threads.is_some()
+            || cache.is_some()
+        {
+            anyhow::bail!(
+                "--book, --tablebase, --level, --network, --threads and --cache only apply to the negamax engine"
+            );
+        }
+        let engine = engine_by_name(engine, seed)
+            .ok_or_else(|| anyhow::anyhow!("unknown engine: {}\n{}", engine, USAGE))?;
+        return Ok((Opponent::Other(engine), options));
+    }
+    let mut ai = match network {
+        Some(network) => AI::with_evaluator(Box::new(network)),
+        None => AI::new(),
+    };
+    if let Some(book) = book {
+        ai = ai.with_book(book);
+    }
+    if let Some(tablebase) = tablebase {
+        ai = ai.with_tablebase(tablebase);
+    }
+    if let Some(threads) = threads {
+        ai = ai.with_threads(threads);
+    }
+    // Missing the first time.
+    if let Some(cache) = cache.filter(|cache| Path::new(cache).exists()) {
+        println!(
+            "Read {} solved positions from {}",
+            ai.load_cache(cache)?,
+            cache
+        );
+    }
+    let ai = ai.with_difficulty(level.unwrap_or(Difficulty::Perfect), seed);
+    Ok((Opponent::Negamax(Box::new(ai), cache), options))
+}
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args[..] {
+        ["book", "--cache", cache, path, max_ply] => make_book(path, max_ply, None, Some(cache)),
+        ["book", "--cache", cache, path, max_ply, moves] => {
+            make_book(path, max_ply, Some(moves), Some(cache))
+        }
+        ["book", path, max_ply] => make_book(path, max_ply, None, None),
+        ["book", path, max_ply, moves] => make_book(path, max_ply, Some(moves), None),
+        ["tablebase", path, empty, moves] => make_tablebase(path, empty, moves),
+        ["match", first, second] => play_match(first, second, None),
+        ["match", first, second, pairs] => play_match(first, second, Some(pairs)),
+        ["train", path, source, count] => train(path, source, count, None),
+        ["train", path, source, count, epochs] => train(path, source, count, Some(epochs)),
+        ["bench", "--threads", threads, ref paths @ ..] if !paths.is_empty() => {
+            bench(paths, Some(threads))
+        }
+        ["bench", ref paths @ ..] if !paths.is_empty() => bench(paths, None),
+        _ => match play_options(&args)? {
+            (Opponent::Negamax(mut ai, cache), options) => {
+                play(&mut *ai, options)?;
+                if let Some(cache) = cache {
+                    ai.save_cache(cache)?;
+                }
+                Ok(())
+            }
+            (Opponent::Other(mut engine), options) => play(&mut *engine, options),
+        },
+    }
+}
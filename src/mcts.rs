@@ -0,0 +1,530 @@
+//! Monte Carlo tree search (UCT): an alternative to the alpha-beta AI, that
+//! plays by sampling games rather than by reading the position.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::ai::ColumnAnalysis;
+use crate::engine::{
+    check_playable, Assessment, Engine, Limits, MoveError, MoveSource, SearchInfo, StopHandle,
+};
+use crate::eval::{playable_cells, winning_cells};
+use crate::explain::{Explanation, Reason};
+use crate::packedboard::*;
+use crate::rng::Rng;
+
+/// How the rest of the game is played from a new node of the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Playout {
+    /// Uniformly random moves.
+    Random,
+    /// Random moves, except that immediate wins are played and the
+    /// opponent's immediate wins are blocked.
+    Heuristic,
+}
+
+/// When to stop searching: after `iterations` playouts or after `time`,
+/// whichever comes first. At least one of them should be set, unless the
+/// search is stopped through [`Engine::stop_handle`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    pub iterations: Option<u64>,
+    pub time: Option<Duration>,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Self {
+            iterations: Some(50_000),
+            time: None,
+        }
+    }
+}
+
+struct Node {
+    board: Board,
+    parent: Option<usize>,
+    /// Moves already expanded, with the index of the resulting node.
+    children: Vec<(ColIdx, usize)>,
+    untried: Vec<ColIdx>,
+    visits: u32,
+    /// Playouts won by the player who moved into this node, draws counting
+    /// for half.
+    wins: f64,
+    /// The game is over in this node: the move into it won or filled the
+    /// board.
+    terminal: Option<PlayoutResult>,
+}
+
+/// Who won a playout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PlayoutResult {
+    Won(NonEmptySqrState),
+    Draw,
+}
+
+impl Node {
+    fn new(board: Board, parent: Option<usize>, terminal: Option<PlayoutResult>) -> Self {
+        let untried = if terminal.is_some() {
+            Vec::new()
+        } else {
+            ALL_COL_IDXS
+                .iter()
+                .copied()
+                .filter(|&coli| !board.col_is_full(coli))
+                .collect()
+        };
+        Self {
+            board,
+            parent,
+            children: Vec::new(),
+            untried,
+            visits: 0,
+            wins: 0.0,
+            terminal,
+        }
+    }
+
+    /// The player who moved into this node.
+    fn mover(&self) -> NonEmptySqrState {
+        self.board.to_play().other()
+    }
+}
+
+pub struct Mcts {
+    /// The tree, `nodes[0]` being the root.
+    nodes: Vec<Node>,
+    rng: Rng,
+    budget: Budget,
+    playout: Playout,
+    /// The `c` of UCT: higher explores more, lower exploits more.
+    exploration: f64,
+    /// Position to search, for [`Engine`].
+    position: Board,
+    stop: Arc<AtomicBool>,
+}
+
+impl Mcts {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            nodes: Vec::new(),
+            rng: Rng::new(seed),
+            budget: Budget::default(),
+            playout: Playout::Heuristic,
+            exploration: std::f64::consts::SQRT_2,
+            position: Board::new(),
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    pub fn with_playout(mut self, playout: Playout) -> Self {
+        self.playout = playout;
+        self
+    }
+
+    pub fn with_exploration(mut self, exploration: f64) -> Self {
+        self.exploration = exploration;
+        self
+    }
+
+    /// Number of playouts that went through the root, including those of
+    /// earlier moves when the tree was reused.
+    pub fn root_visits(&self) -> u32 {
+        self.nodes.first().map_or(0, |root| root.visits)
+    }
+
+    /// Makes the node of `b` the root, keeping its subtree if it was in the
+    /// tree (within the next two moves), starting a new tree otherwise.
+    fn set_root(&mut self, b: &Board) {
+        let mut found = None;
+        if let Some(root) = self.nodes.first() {
+            if root.board == *b {
+                return;
+            }
+            'search: for &(_, child) in &root.children {
+                if self.nodes[child].board == *b {
+                    found = Some(child);
+                    break;
+                }
+                for &(_, grandchild) in &self.nodes[child].children {
+                    if self.nodes[grandchild].board == *b {
+                        found = Some(grandchild);
+                        break 'search;
+                    }
+                }
+            }
+        }
+        let old = std::mem::take(&mut self.nodes);
+        match found {
+            Some(new_root) => {
+                // Move the subtree into a new arena, dropping the rest.
+                let mut old: Vec<Option<Node>> = old.into_iter().map(Some).collect();
+                let mut stack = vec![(new_root, None)];
+                while let Some((old_index, parent)) = stack.pop() {
+                    let mut node = old[old_index].take().unwrap();
+                    let index = self.nodes.len();
+                    node.parent = parent;
+                    for &(_, child) in &node.children {
+                        stack.push((child, Some(index)));
+                    }
+                    node.children.clear();
+                    if let Some(parent) = parent {
+                        let coli = move_between(&self.nodes[parent].board, &node.board);
+                        self.nodes[parent].children.push((coli, index));
+                    }
+                    self.nodes.push(node);
+                }
+            }
+            None => self.nodes.push(Node::new(b.clone(), None, None)),
+        }
+    }
+
+    fn select_child(&self, node: usize) -> usize {
+        let parent = &self.nodes[node];
+        let log_visits = (parent.visits as f64).ln();
+        let uct = |child: &Node| {
+            child.wins / child.visits as f64
+                + self.exploration * (log_visits / child.visits as f64).sqrt()
+        };
+        parent
+            .children
+            .iter()
+            .map(|&(_, child)| child)
+            .max_by(|&a, &b| uct(&self.nodes[a]).total_cmp(&uct(&self.nodes[b])))
+            .unwrap()
+    }
+
+    fn expand(&mut self, node: usize) -> usize {
+        let untried = &mut self.nodes[node].untried;
+        let coli = untried.swap_remove(self.rng.below(untried.len()));
+        let mut board = self.nodes[node].board.clone();
+        let color = board.to_play();
+        let terminal = match board.add_and_check(coli, color) {
+            Ok(true) => Some(PlayoutResult::Won(color)),
+            Ok(false) if board.occupancy() == GRID_SIZE => Some(PlayoutResult::Draw),
+            Ok(false) => None,
+            Err(e) => unreachable!("{}", e),
+        };
+        let index = self.nodes.len();
+        self.nodes.push(Node::new(board, Some(node), terminal));
+        self.nodes[node].children.push((coli, index));
+        index
+    }
+
+    fn playout_move(&mut self, b: &Board) -> ColIdx {
+        let occupied = b.occupied_bitboard();
+        let playable = playable_cells(occupied);
+        if self.playout == Playout::Heuristic {
+            let own = winning_cells(b.bitboard(b.to_play()), occupied) & playable;
+            let other = winning_cells(b.bitboard(b.to_play().other()), occupied) & playable;
+            let urgent = if own != 0 { own } else { other };
+            if urgent != 0 {
+                let col = urgent.trailing_zeros() as u8 / BITBOARD_HEIGHT;
+                return ALL_COL_IDXS[col as usize];
+            }
+        }
+        let legal: Vec<ColIdx> = ALL_COL_IDXS
+            .iter()
+            .copied()
+            .filter(|&coli| !b.col_is_full(coli))
+            .collect();
+        legal[self.rng.below(legal.len())]
+    }
+
+    fn simulate(&mut self, node: usize) -> PlayoutResult {
+        if let Some(result) = self.nodes[node].terminal {
+            return result;
+        }
+        let mut b = self.nodes[node].board.clone();
+        while b.occupancy() < GRID_SIZE {
+            let coli = self.playout_move(&b);
+            let color = b.to_play();
+            if b.add_and_check(coli, color).unwrap() {
+                return PlayoutResult::Won(color);
+            }
+        }
+        PlayoutResult::Draw
+    }
+
+    fn iterate(&mut self) {
+        let mut node = 0;
+        while self.nodes[node].untried.is_empty() && !self.nodes[node].children.is_empty() {
+            node = self.select_child(node);
+        }
+        if !self.nodes[node].untried.is_empty() {
+            node = self.expand(node);
+        }
+        let result = self.simulate(node);
+        let mut current = Some(node);
+        while let Some(index) = current {
+            let node = &mut self.nodes[index];
+            node.visits += 1;
+            node.wins += match result {
+                PlayoutResult::Won(color) if color == node.mover() => 1.0,
+                PlayoutResult::Won(_) => 0.0,
+                PlayoutResult::Draw => 0.5,
+            };
+            current = node.parent;
+        }
+    }
+
+    /// Grows the tree of `b` within `budget`, or until stopped, and returns
+    /// the number of playouts made.
+    fn search(&mut self, b: &Board, budget: Budget) -> u64 {
+        self.set_root(b);
+        let start = Instant::now();
+        let mut iterations = 0;
+        loop {
+            if budget.iterations.is_some_and(|n| iterations >= n)
+                || budget.time.is_some_and(|t| start.elapsed() >= t)
+                || self.stop.load(Ordering::Relaxed)
+            {
+                break;
+            }
+            self.iterate();
+            iterations += 1;
+        }
+        iterations
+    }
+
+    /// Searches `b` within the budget and returns the most visited move, or
+    /// why there is none.
+    pub fn make_a_move(&mut self, b: &Board) -> Result<ColIdx, MoveError> {
+        Ok(self.think(b)?.best_move)
+    }
+
+    /// Chooses a move for `b` like [`Mcts::make_a_move`], and tells how.
+    pub fn think(&mut self, b: &Board) -> Result<SearchInfo, MoveError> {
+        check_playable(b)?;
+        let start = Instant::now();
+        let iterations = self.search(b, self.budget);
+        Ok(self.search_info(iterations, start))
+    }
+
+    fn search_info(&mut self, iterations: u64, start: Instant) -> SearchInfo {
+        if self.nodes[0].children.is_empty() {
+            // Stopped before the first playout.
+            self.iterate();
+        }
+        let mut pv = Vec::new();
+        let mut node = 0;
+        while let Some(&(coli, child)) = self.nodes[node]
+            .children
+            .iter()
+            .max_by_key(|&&(_, child)| self.nodes[child].visits)
+        {
+            pv.push(coli);
+            node = child;
+        }
+        let (best_move, best) = self.nodes[0]
+            .children
+            .iter()
+            .find(|&&(coli, _)| coli == pv[0])
+            .map(|&(coli, child)| (coli, &self.nodes[child]))
+            .unwrap();
+        SearchInfo {
+            best_move,
+            source: MoveSource::Search,
+            score: None,
+            win_rate: Some(best.wins / best.visits as f64),
+            depth: 0,
+            nodes: iterations,
+            tt_hits: 0,
+            tt_cutoffs: 0,
+            elapsed: start.elapsed(),
+            pv,
+        }
+    }
+}
+
+impl Engine for Mcts {
+    fn name(&self) -> &'static str {
+        "mcts"
+    }
+
+    fn new_game(&mut self) {
+        self.nodes.clear();
+        self.position = Board::new();
+    }
+
+    fn set_position(&mut self, b: &Board) {
+        self.position = b.clone();
+    }
+
+    fn go(&mut self, limits: &Limits) -> Result<SearchInfo, MoveError> {
+        self.stop.store(false, Ordering::Relaxed);
+        let start = Instant::now();
+        let b = self.position.clone();
+        check_playable(&b)?;
+        let iterations = self.search(&b, self.limited_budget(limits));
+        Ok(self.search_info(iterations, start))
+    }
+
+    fn stop_handle(&self) -> StopHandle {
+        StopHandle::new(self.stop.clone())
+    }
+
+    /// Grows the tree of the current position within the budget of a move,
+    /// the subtree of the opponent's move being kept for the next `go`.
+    fn ponder(&mut self) {
+        let b = self.position.clone();
+        if check_playable(&b).is_ok() {
+            self.search(&b, self.budget);
+        }
+    }
+
+    /// Columns not sampled yet (with a very small budget) are reported with
+    /// no playouts and a meaningless win rate.
+    fn analyze(&mut self, limits: &Limits) -> Result<[Assessment; NCOL as usize], MoveError> {
+        self.stop.store(false, Ordering::Relaxed);
+        let b = self.position.clone();
+        check_playable(&b)?;
+        self.search(&b, self.limited_budget(limits));
+        let root = &self.nodes[0];
+        Ok(ALL_COL_IDXS.map(|coli| {
+            if b.col_is_full(coli) {
+                return Assessment::Searched(ColumnAnalysis::Full);
+            }
+            match root.children.iter().find(|&&(c, _)| c == coli) {
+                Some(&(_, child)) => {
+                    let child = &self.nodes[child];
+                    Assessment::Sampled {
+                        win_rate: child.wins / child.visits as f64,
+                        playouts: child.visits,
+                    }
+                }
+                None => Assessment::Sampled {
+                    win_rate: 0.0,
+                    playouts: 0,
+                },
+            }
+        }))
+    }
+
+    /// The tactics of the move, and its share of won playouts if the tree
+    /// of the current position has any: nothing is searched.
+    fn explain(&mut self, col: ColIdx, _limits: &Limits) -> Explanation {
+        let mut res = Explanation::tactical(&self.position, col);
+        if res.reasons == [Reason::WinsNow] || res.reasons == [Reason::ColumnFull] {
+            return res;
+        }
+        let root = match self.nodes.first() {
+            Some(root) if root.board == self.position => root,
+            _ => return res,
+        };
+        if let Some(&(_, child)) = root.children.iter().find(|&&(c, _)| c == col) {
+            let child = &self.nodes[child];
+            res.reasons.push(Reason::WinRate {
+                win_rate: child.wins / child.visits as f64,
+            });
+        }
+        res
+    }
+}
+
+impl Mcts {
+    /// The budget, with the parts set in `limits` replaced.
+    fn limited_budget(&self, limits: &Limits) -> Budget {
+        if limits.iterations.is_none() && limits.time.is_none() {
+            return self.budget;
+        }
+        Budget {
+            iterations: limits.iterations,
+            time: limits.time,
+        }
+    }
+}
+
+/// The move from `parent` to `child`, one token apart.
+fn move_between(parent: &Board, child: &Board) -> ColIdx {
+    *ALL_COL_IDXS
+        .iter()
+        .find(|&&coli| parent.col_len(coli) != child.col_len(coli))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mcts(iterations: u64) -> Mcts {
+        Mcts::new(7).with_budget(Budget {
+            iterations: Some(iterations),
+            time: None,
+        })
+    }
+
+    #[test]
+    fn takes_immediate_win() {
+        // Red completes its bottom row in column 4.
+        let b = Board::from_moves("112233").unwrap();
+        for &playout in &[Playout::Random, Playout::Heuristic] {
+            let coli = mcts(2000).with_playout(playout).make_a_move(&b).unwrap();
+            assert_eq!(coli, ALL_COL_IDXS[3]);
+        }
+    }
+
+    #[test]
+    fn blocks_immediate_loss() {
+        // Yellow must block Red's vertical line in column 1.
+        let b = Board::from_moves("12121").unwrap();
+        assert_eq!(mcts(3000).make_a_move(&b).unwrap(), ALL_COL_IDXS[0]);
+    }
+
+    #[test]
+    fn reuses_the_tree() {
+        let mut m = mcts(500);
+        let mut b = Board::from_moves("44").unwrap();
+        let coli = m.make_a_move(&b).unwrap();
+        b.add_to_col(coli, b.to_play()).unwrap();
+        b.add_to_col(ALL_COL_IDXS[0], b.to_play()).unwrap();
+        m.set_root(&b);
+        let kept = m.root_visits();
+        assert!(kept > 0);
+        m.make_a_move(&b).unwrap();
+        assert_eq!(m.root_visits(), kept + 500);
+        assert!(m
+            .nodes
+            .iter()
+            .all(|node| node.board.occupancy() >= b.occupancy()));
+    }
+
+    #[test]
+    fn think_counts_playouts() {
+        let b = Board::from_moves("4453").unwrap();
+        let info = mcts(300).think(&b).unwrap();
+        assert_eq!(info.nodes, 300);
+        assert_eq!(info.pv[0], info.best_move);
+        assert!((0.0..=1.0).contains(&info.win_rate.unwrap()));
+    }
+
+    #[test]
+    fn keeps_the_pondered_tree() {
+        let mut m = mcts(500);
+        let b = Board::from_moves("4").unwrap();
+        m.search(
+            &b,
+            Budget {
+                iterations: Some(300),
+                time: None,
+            },
+        );
+        let mut reply = b.clone();
+        reply.add_to_col(ALL_COL_IDXS[3], b.to_play()).unwrap();
+        m.set_root(&reply);
+        assert!(m.root_visits() > 0);
+    }
+
+    #[test]
+    fn same_seed_same_move() {
+        let b = Board::from_moves("4453").unwrap();
+        let moves: Vec<_> = (0..2).map(|_| mcts(300).make_a_move(&b).unwrap()).collect();
+        assert_eq!(moves[0], moves[1]);
+    }
+}
@@ -0,0 +1,398 @@
+//! Neural network evaluation: a small multilayer perceptron over the two
+//! bitboards of a position, trained on positions labelled by the result of
+//! self-play games or by the solver.
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::ai::{Outcome, AI};
+use crate::engine::{Engine, Limits};
+use crate::eval::{Evaluate, MAX_EVAL};
+use crate::packedboard::*;
+use crate::rng::Rng;
+use crate::solved::invalid_data;
+
+const MAGIC: &[u8; 8] = b"F4RNNET\0";
+const VERSION: u8 = 1;
+
+/// One input per cell for the tokens of the player about to move, then one
+/// per cell for the tokens of the other player.
+pub const INPUTS: usize = 2 * GRID_SIZE as usize;
+/// Size of the hidden layer of [`Network::new`] in the training command.
+pub const DEFAULT_HIDDEN: usize = 32;
+/// Number of moves played at random at the start of each self-play game, so
+/// that the games differ.
+pub const RANDOM_PLIES: u8 = 6;
+
+/// A position with the value the network should learn for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub board: Board,
+    /// For the player about to move: 1 for a win, -1 for a loss, 0 for a
+    /// draw.
+    pub target: f32,
+}
+
+/// Settings of [`Network::train`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Training {
+    pub epochs: u32,
+    pub learning_rate: f32,
+    /// Seed of the order the samples are seen in.
+    pub seed: u64,
+}
+
+impl Default for Training {
+    fn default() -> Self {
+        Self {
+            epochs: 20,
+            learning_rate: 0.01,
+            seed: 0,
+        }
+    }
+}
+
+/// One hidden layer of rectified linear units and a `tanh` output, the
+/// value of the position for the player about to move.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Network {
+    hidden: usize,
+    /// `INPUTS` rows of `hidden` weights, so that the few inputs that are
+    /// set add up contiguous rows.
+    w1: Vec<f32>,
+    b1: Vec<f32>,
+    w2: Vec<f32>,
+    b2: f32,
+}
+
+/// Inputs set for `b` seen by `color`, see [`INPUTS`].
+fn active_inputs(b: &Board, color: NonEmptySqrState) -> Vec<usize> {
+    let mut res = Vec::with_capacity(b.occupancy() as usize);
+    for (offset, bitboard) in [
+        (0, b.bitboard(color)),
+        (GRID_SIZE, b.bitboard(color.other())),
+    ] {
+        let mut tokens = bitboard;
+        while tokens != 0 {
+            let bit = tokens.trailing_zeros() as u8;
+            let (coli, rowi) = (bit / BITBOARD_HEIGHT, bit % BITBOARD_HEIGHT);
+            res.push((offset + coli * NROW + rowi) as usize);
+            tokens &= tokens - 1;
+        }
+    }
+    res
+}
+
+impl Network {
+    /// A network with random weights.
+    pub fn new(hidden: usize, seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
+        let mut uniform = |fan_in: usize, len: usize| -> Vec<f32> {
+            let bound = (3.0 / fan_in as f64).sqrt();
+            (0..len)
+                .map(|_| ((2.0 * rng.next_f64() - 1.0) * bound) as f32)
+                .collect()
+        };
+        // About a fifth of the cells are set on average.
+        let w1 = uniform(INPUTS / 5, INPUTS * hidden);
+        let w2 = uniform(hidden, hidden);
+        Self {
+            hidden,
+            w1,
+            b1: vec![0.0; hidden],
+            w2,
+            b2: 0.0,
+        }
+    }
+
+    pub fn hidden(&self) -> usize {
+        self.hidden
+    }
+
+    /// Activations of the hidden layer, before the rectifier, and output.
+    fn forward(&self, inputs: &[usize]) -> (Vec<f32>, f32) {
+        let mut hidden = self.b1.clone();
+        for &i in inputs {
+            let row = &self.w1[i * self.hidden..(i + 1) * self.hidden];
+            for (h, w) in hidden.iter_mut().zip(row) {
+                *h += w;
+            }
+        }
+        let z = self.b2
+            + hidden
+                .iter()
+                .zip(&self.w2)
+                .map(|(h, w)| h.max(0.0) * w)
+                .sum::<f32>();
+        (hidden, z.tanh())
+    }
+
+    /// Value of `b` for the player about to move, in `(-1, 1)`.
+    pub fn value(&self, b: &Board) -> f32 {
+        self.forward(&active_inputs(b, b.to_play())).1
+    }
+
+    /// Half the mean squared error of the network on `samples`.
+    pub fn loss(&self, samples: &[Sample]) -> f32 {
+        let total: f32 = samples
+            .iter()
+            .map(|s| (self.value(&s.board) - s.target).powi(2) / 2.0)
+            .sum();
+        total / samples.len().max(1) as f32
+    }
+
+    /// One step of gradient descent on `sample`, returning its loss before
+    /// the step.
+    fn step(&mut self, sample: &Sample, learning_rate: f32) -> f32 {
+        let inputs = active_inputs(&sample.board, sample.board.to_play());
+        let (hidden, out) = self.forward(&inputs);
+        let error = out - sample.target;
+        let dz = error * (1.0 - out * out);
+        for (j, &h) in hidden.iter().enumerate() {
+            if h <= 0.0 {
+                continue;
+            }
+            let dh = dz * self.w2[j];
+            self.w2[j] -= learning_rate * dz * h;
+            self.b1[j] -= learning_rate * dh;
+            for &i in &inputs {
+                self.w1[i * self.hidden + j] -= learning_rate * dh;
+            }
+        }
+        self.b2 -= learning_rate * dz;
+        error * error / 2.0
+    }
+
+    /// Stochastic gradient descent on `samples`. `progress` is called after
+    /// each epoch with its number, from 1, and the mean loss over it.
+    pub fn train(
+        &mut self,
+        samples: &[Sample],
+        training: &Training,
+        mut progress: impl FnMut(u32, f32),
+    ) {
+        let mut rng = Rng::new(training.seed);
+        let mut order: Vec<usize> = (0..samples.len()).collect();
+        for epoch in 1..=training.epochs {
+            for i in (1..order.len()).rev() {
+                order.swap(i, rng.below(i + 1));
+            }
+            let total: f32 = order
+                .iter()
+                .map(|&i| self.step(&samples[i], training.learning_rate))
+                .sum();
+            progress(epoch, total / samples.len().max(1) as f32);
+        }
+    }
+
+    /// Writes the network: a header made of a magic string, the format
+    /// version and the size of the hidden layer, then the weights and biases
+    /// of each layer as little-endian `f32`.
+    pub fn write_to(&self, mut w: impl Write) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&[VERSION])?;
+        w.write_all(&(self.hidden as u32).to_le_bytes())?;
+        for x in self.w1.iter().chain(&self.b1).chain(&self.w2) {
+            w.write_all(&x.to_le_bytes())?;
+        }
+        w.write_all(&self.b2.to_le_bytes())?;
+        w.flush()
+    }
+
+    pub fn read_from(mut r: impl Read) -> io::Result<Self> {
+        let mut header = [0; 13];
+        r.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(invalid_data("not a neural network"));
+        }
+        if header[8] != VERSION {
+            return Err(invalid_data("unsupported neural network version"));
+        }
+        let hidden = u32::from_le_bytes(header[9..].try_into().unwrap()) as usize;
+        if hidden == 0 || hidden > 1 << 16 {
+            return Err(invalid_data("invalid hidden layer size"));
+        }
+        let mut read = |len: usize| -> io::Result<Vec<f32>> {
+            let mut bytes = vec![0; 4 * len];
+            r.read_exact(&mut bytes)?;
+            let values: Vec<f32> = bytes
+                .chunks_exact(4)
+                .map(|x| f32::from_le_bytes(x.try_into().unwrap()))
+                .collect();
+            if !values.iter().all(|x| x.is_finite()) {
+                return Err(invalid_data("weight is not a finite number"));
+            }
+            Ok(values)
+        };
+        let w1 = read(INPUTS * hidden)?;
+        let b1 = read(hidden)?;
+        let w2 = read(hidden)?;
+        let b2 = read(1)?[0];
+        Ok(Self {
+            hidden,
+            w1,
+            b1,
+            w2,
+            b2,
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+impl Evaluate for Network {
+    fn evaluate(&self, b: &Board, color: NonEmptySqrState) -> i16 {
+        let value = self.forward(&active_inputs(b, color)).1;
+        (value * MAX_EVAL as f32).round() as i16
+    }
+}
+
+/// Plays a random legal move for the player about to move, and tells whether
+/// it won.
+fn random_move(b: &mut Board, rng: &mut Rng) -> bool {
+    let color = b.to_play();
+    loop {
+        match b.add_and_check(ALL_COL_IDXS[rng.below(NCOL as usize)], color) {
+            Ok(won) => return won,
+            Err(_) => continue,
+        }
+    }
+}
+
+/// The positions of `games` games of an AI searching `depth` moves ahead
+/// against itself, each labelled with the result of its game. The first
+/// [`RANDOM_PLIES`] moves of each game are random.
+pub fn self_play_samples(games: usize, depth: u8, seed: u64) -> Vec<Sample> {
+    let mut rng = Rng::new(seed);
+    let mut ai = AI::new();
+    let limits = Limits {
+        depth: Some(depth),
+        ..Limits::default()
+    };
+    let mut samples = Vec::new();
+    for _ in 0..games {
+        ai.new_game();
+        let mut b = Board::new();
+        let mut positions = Vec::new();
+        let mut winner = None;
+        while b.occupancy() < GRID_SIZE {
+            positions.push(b.clone());
+            let color = b.to_play();
+            let won = if b.occupancy() < RANDOM_PLIES {
+                random_move(&mut b, &mut rng)
+            } else {
+                ai.set_position(&b);
+                let coli = ai.go(&limits).expect("the game is not over").best_move;
+                b.add_and_check(coli, color).unwrap()
+            };
+            if won {
+                winner = Some(color);
+                break;
+            }
+        }
+        samples.extend(positions.into_iter().map(|board| {
+            let target = match winner {
+                Some(color) if color == board.to_play() => 1.0,
+                Some(_) => -1.0,
+                None => 0.0,
+            };
+            Sample { board, target }
+        }));
+    }
+    samples
+}
+
+/// `count` positions with `empty` empty cells reached by random moves,
+/// labelled with their value by the solver.
+pub fn solved_samples(count: usize, empty: u8, seed: u64) -> Vec<Sample> {
+    let mut rng = Rng::new(seed);
+    let mut ai = AI::new();
+    let mut samples = Vec::with_capacity(count);
+    while samples.len() < count {
+        let mut b = Board::new();
+        let mut won = false;
+        while !won && b.occupancy() < GRID_SIZE - empty {
+            won = random_move(&mut b, &mut rng);
+        }
+        if won {
+            continue;
+        }
+        let target = match ai.weak_solve(&b) {
+            Ok(Outcome::Win) => 1.0,
+            Ok(Outcome::Draw) => 0.0,
+            Ok(Outcome::Loss) => -1.0,
+            // Full, with no empty cell asked for.
+            Err(_) => continue,
+        };
+        samples.push(Sample { board: b, target });
+    }
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inputs_follow_the_player_to_move() {
+        let b = Board::from_moves("4453").unwrap();
+        let red = active_inputs(&b, NonEmptySqrState::Red);
+        assert_eq!(red.len(), 4);
+        // Red has the bottom of columns 4 and 5, Yellow the bottom of
+        // column 3 and the cell above Red in column 4.
+        assert_eq!(red[..2], [3 * 6, 4 * 6]);
+        assert_eq!(red[2..], [42 + 2 * 6, 42 + 3 * 6 + 1]);
+        let yellow = active_inputs(&b, NonEmptySqrState::Yellow);
+        assert_eq!(yellow[..2], [2 * 6, 3 * 6 + 1]);
+        assert_eq!(yellow[2..], [42 + 3 * 6, 42 + 4 * 6]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let network = Network::new(8, 1);
+        let mut bytes = Vec::new();
+        network.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 13 + 4 * (INPUTS * 8 + 8 + 8 + 1));
+        assert_eq!(Network::read_from(&bytes[..]).unwrap(), network);
+        bytes[0] = b'X';
+        assert!(Network::read_from(&bytes[..]).is_err());
+        assert!(Network::read_from(&bytes[..20]).is_err());
+    }
+
+    #[test]
+    fn learns_solved_positions() {
+        let samples = solved_samples(200, 12, 0);
+        let mut network = Network::new(16, 0);
+        let before = network.loss(&samples);
+        let mut losses = Vec::new();
+        network.train(&samples, &Training::default(), |_, loss| losses.push(loss));
+        assert_eq!(losses.len(), 20);
+        assert!(network.loss(&samples) < before / 2.0, "{:?}", losses);
+        let b = &samples[0].board;
+        let eval = network.evaluate(b, b.to_play());
+        assert!(eval.abs() <= MAX_EVAL);
+        let mut ai = AI::with_evaluator(Box::new(network));
+        assert!(!b.col_is_full(ai.make_a_move(b).unwrap()));
+    }
+
+    #[test]
+    fn self_play_labels_each_game() {
+        let samples = self_play_samples(2, 2, 0);
+        assert!(samples.len() > 2 * RANDOM_PLIES as usize);
+        assert_eq!(samples[0].board, Board::new());
+        // Consecutive positions of a game have opposite values.
+        for pair in samples.windows(2) {
+            if pair[1].board.occupancy() == pair[0].board.occupancy() + 1 {
+                assert_eq!(pair[0].target, -pair[1].target);
+            }
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use std::fmt;
 use std::{char, convert::TryInto};
 
+use ahash::AHashSet;
+
 #[derive(Eq, PartialEq, Copy, Clone, Debug, Hash)]
 pub enum NonEmptySqrState {
     Red,
@@ -29,8 +31,9 @@ impl std::fmt::Display for NonEmptySqrState {
     }
 }
 
-#[derive(Eq, PartialEq, Copy, Clone, Debug, Hash)]
+#[derive(Eq, PartialEq, Copy, Clone, Debug, Hash, Default)]
 pub enum SqrState {
+    #[default]
     Empty,
     NonEmpty(NonEmptySqrState),
 }
@@ -49,12 +52,6 @@ impl SqrState {
     }
 }
 
-impl Default for SqrState {
-    fn default() -> SqrState {
-        SqrState::Empty
-    }
-}
-
 impl fmt::Display for SqrState {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.to_char())
@@ -63,24 +60,12 @@ impl fmt::Display for SqrState {
 
 pub const NCOL: u8 = 7;
 pub const NROW: u8 = 6;
-pub const ALL_COL_IDXS : [ColIdx; NCOL as usize] = [
-    Idx(0),
-    Idx(1),
-    Idx(2),
-    Idx(3),
-    Idx(4),
-    Idx(5),
-    Idx(6),
-];
-pub const ALL_ROW_IDXS : [ColIdx; NROW as usize] = [
-    Idx(0),
-    Idx(1),
-    Idx(2),
-    Idx(3),
-    Idx(4),
-    Idx(5),
-];
-const GRID_SIZE: u8 = NCOL * NROW;
+pub const ALL_COL_IDXS: [ColIdx; NCOL as usize] =
+    [Idx(0), Idx(1), Idx(2), Idx(3), Idx(4), Idx(5), Idx(6)];
+pub const ALL_ROW_IDXS: [ColIdx; NROW as usize] = [Idx(0), Idx(1), Idx(2), Idx(3), Idx(4), Idx(5)];
+pub const GRID_SIZE: u8 = NCOL * NROW;
+/// Height of a column in the [`Board::bitboard`] layout.
+pub const BITBOARD_HEIGHT: u8 = NROW + 1;
 const LEN_SIZE: u8 = 3;
 
 #[derive(Debug)]
@@ -95,6 +80,12 @@ pub enum BoardError {
         column_index: ColIdx,
         tried_to_push: NonEmptySqrState,
     },
+    InvalidMove {
+        mv: char,
+    },
+    MoveAfterWin {
+        move_index: usize,
+    },
 }
 
 impl std::fmt::Display for BoardError {
@@ -116,6 +107,14 @@ impl std::fmt::Display for BoardError {
                 "Column {} is already full, cannot add {} token.",
                 column_index, tried_to_push
             )),
+            InvalidMove { mv } => f.write_fmt(format_args!(
+                "Move {:?} is not a column number between 1 and {}",
+                mv, NCOL
+            )),
+            MoveAfterWin { move_index } => f.write_fmt(format_args!(
+                "Move n°{} is played after the game was won",
+                move_index + 1
+            )),
         }
     }
 }
@@ -124,7 +123,7 @@ impl std::error::Error for BoardError {}
 
 pub type BoardResult<T> = Result<T, BoardError>;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Idx<const MAX: u8>(u8);
 pub type ColIdx = Idx<NCOL>;
 pub type RowIdx = Idx<NROW>;
@@ -155,6 +154,37 @@ impl<const MAX: u8> Idx<MAX> {
     }
 }
 
+/// Writes `moves` the way [`Board::from_moves`] reads them.
+pub fn moves_to_string(moves: &[ColIdx]) -> String {
+    moves
+        .iter()
+        .map(|coli| (coli.get() + 1).to_string())
+        .collect()
+}
+
+/// The column `coli` becomes in the [mirror image](Board::mirrored).
+pub fn mirror_col(coli: ColIdx) -> ColIdx {
+    ALL_COL_IDXS[(NCOL - 1 - coli.get()) as usize]
+}
+
+/// The positions one move after those of `positions` that are not already
+/// won, once for each pair of mirror images.
+pub fn next_positions(positions: &[Board]) -> Vec<Board> {
+    let mut seen = AHashSet::new();
+    let mut next = Vec::new();
+    for b in positions {
+        for &coli in &ALL_COL_IDXS {
+            let mut child = b.clone();
+            if let Ok(false) = child.add_and_check(coli, b.to_play()) {
+                if seen.insert(child.canonical_key().0) {
+                    next.push(child);
+                }
+            }
+        }
+    }
+    next
+}
+
 fn get_bits<S, L>(x: u64, start_from_right: S, length: L) -> u64
 where
     u64: std::ops::Shr<S, Output = u64>,
@@ -190,14 +220,60 @@ impl Board {
         Self(1 << 63)
     }
 
+    /// Plays `moves`, a sequence of 1-based column numbers such as "4453",
+    /// from the empty board.
+    pub fn from_moves(moves: &str) -> BoardResult<Self> {
+        let mut b = Self::new();
+        let mut won = false;
+        for (move_index, mv) in moves.chars().enumerate() {
+            if won {
+                return Err(BoardError::MoveAfterWin { move_index });
+            }
+            let coli = mv
+                .to_digit(10)
+                .and_then(|d| ColIdx::new((d as u8).wrapping_sub(1)))
+                .ok_or(BoardError::InvalidMove { mv })?;
+            won = b.add_and_check(coli, b.to_play())?;
+        }
+        Ok(b)
+    }
+
+    /// The packed representation of the board, which identifies it.
+    pub fn key(&self) -> u64 {
+        self.0
+    }
+
+    /// The board whose [`Board::key`] is `key`, if it is the key of a board.
+    pub fn from_key(key: u64) -> Option<Self> {
+        let mut b = Self::new();
+        for &coli in &ALL_COL_IDXS {
+            let len_start = GRID_SIZE + coli.get() * LEN_SIZE;
+            let len = get_bits(key, len_start, LEN_SIZE);
+            if len > NROW.into() {
+                return None;
+            }
+            set_bits(&mut b.0, len, len_start, LEN_SIZE);
+            if len > 0 {
+                let col = get_bits(key, coli.get() * NROW, len as usize);
+                set_bits(&mut b.0, col, coli.get() * NROW, len as usize);
+            }
+        }
+        // Anything else set is not part of a board.
+        if b.0 == key {
+            Some(b)
+        } else {
+            None
+        }
+    }
+
     pub fn check_col_idx(idx: u8) -> BoardResult<ColIdx> {
-        ColIdx::new(idx).ok_or(BoardError::ColumnIndexOutOfBounds{
+        ColIdx::new(idx).ok_or(BoardError::ColumnIndexOutOfBounds {
             required_index: idx as usize,
         })
     }
 
     pub fn check_row_idx(idx: u8) -> BoardResult<RowIdx> {
-        RowIdx::new(idx).ok_or(BoardError::RowIndexOutOfBounds{
+        RowIdx::new(idx).ok_or(BoardError::RowIndexOutOfBounds {
             required_index: idx as usize,
         })
     }
@@ -212,6 +288,15 @@ impl Board {
         ALL_COL_IDXS.iter().map(|&c| self.col_len(c)).sum()
     }
 
+    /// Color of the player whose turn it is, Red having played first.
+    pub fn to_play(&self) -> NonEmptySqrState {
+        if self.occupancy().is_multiple_of(2) {
+            NonEmptySqrState::Red
+        } else {
+            NonEmptySqrState::Yellow
+        }
+    }
+
     pub fn col_first_free_row(&self, coli: ColIdx) -> Option<RowIdx> {
         RowIdx::new(self.col_len(coli))
     }
@@ -265,7 +350,7 @@ impl Board {
     pub fn win_at(&self, col_i: ColIdx, row_i: RowIdx) -> (SqrState, bool) {
         let state = self.get_cell(col_i, row_i);
         match state {
-            SqrState::Empty => return (state, false),
+            SqrState::Empty => (state, false),
             SqrState::NonEmpty(_) => {
                 for (cdir, rdir) in &[(1, 0), (1, 1), (0, 1), (-1, 1)] {
                     let mut count = 0;
@@ -283,7 +368,7 @@ impl Board {
                         return (state, true);
                     }
                 }
-                return (state, false);
+                (state, false)
             }
         }
     }
@@ -292,6 +377,87 @@ impl Board {
         let rowi = self.add_to_col(coli, color)?;
         Ok(self.win_at(coli, rowi).1)
     }
+
+    /// The board seen in a mirror: column `i` becomes column `NCOL - 1 - i`.
+    pub fn mirrored(&self) -> Self {
+        let mut res = Self::new();
+        for &coli in &ALL_COL_IDXS {
+            let target = NCOL - 1 - coli.get();
+            let len = self.col_len(coli);
+            set_bits(
+                &mut res.0,
+                len.into(),
+                GRID_SIZE + target * LEN_SIZE,
+                LEN_SIZE,
+            );
+            if len > 0 {
+                let col = get_bits(self.0, coli.get() * NROW, len as usize);
+                set_bits(&mut res.0, col, target * NROW, len as usize);
+            }
+        }
+        res
+    }
+
+    /// Smallest of the keys of the board and of its mirror image, and
+    /// whether it is the key of the mirror image: the same for both, so it
+    /// identifies the pair.
+    pub fn canonical_key(&self) -> (u64, bool) {
+        let mirrored = self.mirrored().key();
+        if mirrored < self.key() {
+            (mirrored, true)
+        } else {
+            (self.key(), false)
+        }
+    }
+
+    /// Tokens of `color` as a bitboard: cell (`coli`, `rowi`) is bit
+    /// `coli * BITBOARD_HEIGHT + rowi`. The extra row on top of each column
+    /// is always zero, so shifting by a multiple of `BITBOARD_HEIGHT` never
+    /// carries a line over from one column into the next.
+    pub fn bitboard(&self, color: NonEmptySqrState) -> u64 {
+        let mut res = 0;
+        for &coli in &ALL_COL_IDXS {
+            let len = self.col_len(coli);
+            if len == 0 {
+                continue;
+            }
+            let yellows = get_bits(self.0, coli.get() * NROW, len as usize);
+            let col = match color {
+                NonEmptySqrState::Yellow => yellows,
+                NonEmptySqrState::Red => !yellows & ((1 << len) - 1),
+            };
+            res |= col << (coli.get() * BITBOARD_HEIGHT);
+        }
+        res
+    }
+
+    /// The player with four tokens in a line, if any.
+    pub fn winner(&self) -> Option<NonEmptySqrState> {
+        let h = BITBOARD_HEIGHT;
+        [NonEmptySqrState::Red, NonEmptySqrState::Yellow]
+            .iter()
+            .copied()
+            .find(|&color| {
+                let own = self.bitboard(color);
+                [1, h, h - 1, h + 1].iter().any(|&step| {
+                    let pairs = own & (own >> step);
+                    pairs & (pairs >> (2 * step)) != 0
+                })
+            })
+    }
+
+    /// Bitboard of all the tokens on the board, see [`Board::bitboard`].
+    pub fn occupied_bitboard(&self) -> u64 {
+        ALL_COL_IDXS.iter().fold(0, |acc, &coli| {
+            acc | (((1 << self.col_len(coli)) - 1) << (coli.get() * BITBOARD_HEIGHT))
+        })
+    }
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 //impl Index<usize> for Board {
@@ -334,3 +500,158 @@ impl fmt::Display for Board {
         write!(f, "{}", res)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    const GAMES: [&str; 4] = [
+        "",
+        "4453",
+        "2252576253462244111563365343671351",
+        "676267454774755615735426464532211123113332",
+    ];
+
+    /// Plays `moves` one token at a time, without [`Board::from_moves`].
+    fn play(moves: &[ColIdx]) -> Board {
+        let mut b = Board::new();
+        let mut color = NonEmptySqrState::Red;
+        for &coli in moves {
+            b.add_to_col(coli, color).unwrap();
+            color = color.other();
+        }
+        b
+    }
+
+    fn cols(moves: &str) -> Vec<ColIdx> {
+        moves
+            .chars()
+            .map(|c| ALL_COL_IDXS[c.to_digit(10).unwrap() as usize - 1])
+            .collect()
+    }
+
+    #[test]
+    fn moves_round_trip() {
+        for moves in &GAMES {
+            assert_eq!(moves_to_string(&cols(moves)), *moves);
+            let b = Board::from_moves(moves).unwrap();
+            assert_eq!(b, play(&cols(moves)));
+            assert_eq!(b.occupancy() as usize, moves.len());
+        }
+    }
+
+    #[test]
+    fn invalid_moves() {
+        for moves in &["0", "8", "44a", "4 4", "-1"] {
+            assert!(
+                matches!(
+                    Board::from_moves(moves),
+                    Err(BoardError::InvalidMove { .. })
+                ),
+                "{:?}",
+                moves
+            );
+        }
+        assert!(matches!(
+            Board::from_moves("1111111"),
+            Err(BoardError::ColumnFull { .. })
+        ));
+        assert!(matches!(
+            Board::from_moves("12121214"),
+            Err(BoardError::MoveAfterWin { move_index: 7 })
+        ));
+        // The winning move itself is fine.
+        assert!(Board::from_moves("1212121").is_ok());
+    }
+
+    #[test]
+    fn key_round_trip() {
+        for moves in &GAMES {
+            let b = Board::from_moves(moves).unwrap();
+            assert_eq!(Board::from_key(b.key()), Some(b.clone()));
+        }
+        let b = Board::from_moves("4453").unwrap();
+        // A column of 7 tokens.
+        let mut too_long = b.key();
+        set_bits(&mut too_long, 7, GRID_SIZE, LEN_SIZE);
+        // A token above the top of its column.
+        let stray = b.key() | 1 << (3 * NROW + 5);
+        for &key in &[0, too_long, stray] {
+            assert_eq!(Board::from_key(key), None, "{:#x}", key);
+        }
+    }
+
+    #[test]
+    fn mirrored() {
+        let b = Board::from_moves("1234").unwrap();
+        assert_eq!(b.mirrored(), Board::from_moves("7654").unwrap());
+        for moves in &GAMES {
+            let b = Board::from_moves(moves).unwrap();
+            let mirrored: String = moves
+                .chars()
+                .map(|c| char::from_digit(8 - c.to_digit(10).unwrap(), 10).unwrap())
+                .collect();
+            assert_eq!(b.mirrored(), Board::from_moves(&mirrored).unwrap());
+            assert_eq!(b.mirrored().mirrored(), b);
+            assert_eq!(b.mirrored().to_play(), b.to_play());
+        }
+    }
+
+    #[test]
+    fn next_positions_by_mirror_class() {
+        let b = Board::from_moves("1234").unwrap();
+        let (key, mirrored) = b.canonical_key();
+        assert_eq!(key, b.key().min(b.mirrored().key()));
+        assert_eq!(b.mirrored().canonical_key(), (key, !mirrored));
+        let first = next_positions(&[Board::new()]);
+        assert_eq!(first.len(), 4);
+        // Of the 49 positions after two moves, only 44 is its own mirror
+        // image.
+        assert_eq!(next_positions(&first).len(), 25);
+        for &coli in &ALL_COL_IDXS {
+            assert_eq!(mirror_col(mirror_col(coli)), coli);
+        }
+        assert_eq!(mirror_col(ALL_COL_IDXS[0]), ALL_COL_IDXS[6]);
+    }
+
+    #[test]
+    fn winner_and_player_to_move() {
+        assert_eq!(Board::new().winner(), None);
+        assert_eq!(
+            Board::from_moves("1212121").unwrap().winner(),
+            Some(NonEmptySqrState::Red)
+        );
+        assert_eq!(
+            Board::from_moves("31212151").unwrap().winner(),
+            Some(NonEmptySqrState::Yellow)
+        );
+        // Random games, checked against the winning lines found by
+        // `add_and_check`.
+        let mut rng = Rng::new(0);
+        for _ in 0..200 {
+            let mut b = Board::new();
+            loop {
+                assert_eq!(b.winner(), None);
+                let color = b.to_play();
+                let coli = ALL_COL_IDXS[rng.below(NCOL as usize)];
+                let occupancy = b.occupancy();
+                match b.add_and_check(coli, color) {
+                    Err(_) => continue,
+                    Ok(won) => {
+                        assert_eq!(b.occupancy(), occupancy + 1);
+                        assert_ne!(b.to_play(), color);
+                        if won {
+                            assert_eq!(b.winner(), Some(color));
+                            break;
+                        }
+                    }
+                }
+                if b.occupancy() == GRID_SIZE {
+                    assert_eq!(b.winner(), None);
+                    break;
+                }
+            }
+        }
+    }
+}
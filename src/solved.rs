@@ -0,0 +1,124 @@
+//! Tables of solved positions, shared by the opening book and the endgame
+//! tablebase: the exact value and a best move of each position, stored once
+//! for each pair of mirror images, and the file format they are saved in.
+
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+
+use crate::ai::Score;
+use crate::packedboard::*;
+
+/// Magic string, format version, a byte of the owner and number of entries.
+pub(crate) const HEADER_SIZE: usize = 18;
+/// Key, score and column.
+pub(crate) const ENTRY_SIZE: usize = 11;
+
+pub(crate) fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Entry {
+    /// [`Board::canonical_key`] of the position.
+    key: u64,
+    score: Score,
+    /// Best move, in the orientation given by `key`.
+    col: ColIdx,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct SolvedTable {
+    /// Sorted by key.
+    entries: Vec<Entry>,
+}
+
+impl SolvedTable {
+    /// The table of `solved`, made of positions with a best move and their
+    /// exact value. Of two mirror images, the first one is kept.
+    pub(crate) fn new(solved: impl IntoIterator<Item = (Board, ColIdx, Score)>) -> Self {
+        let mut entries: Vec<Entry> = solved
+            .into_iter()
+            .map(|(b, col, score)| {
+                let (key, mirrored) = b.canonical_key();
+                let col = if mirrored { mirror_col(col) } else { col };
+                Entry { key, score, col }
+            })
+            .collect();
+        entries.sort_by_key(|e| e.key);
+        entries.dedup_by_key(|e| e.key);
+        Self { entries }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Best move and exact value of `b`, if it is in the table.
+    pub(crate) fn lookup(&self, b: &Board) -> Option<(ColIdx, Score)> {
+        let (key, mirrored) = b.canonical_key();
+        let entry = self.entries[self.entries.binary_search_by_key(&key, |e| e.key).ok()?];
+        let col = if mirrored {
+            mirror_col(entry.col)
+        } else {
+            entry.col
+        };
+        Some((col, entry.score))
+    }
+
+    /// Writes the table: a header made of `magic`, `version`, `param` and
+    /// the number of entries, then [`ENTRY_SIZE`] bytes per entry, sorted so
+    /// that they can be searched by key.
+    pub(crate) fn write_to(
+        &self,
+        mut w: impl Write,
+        magic: &[u8; 8],
+        version: u8,
+        param: u8,
+    ) -> io::Result<()> {
+        w.write_all(magic)?;
+        w.write_all(&[version, param])?;
+        w.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+        for entry in &self.entries {
+            w.write_all(&entry.key.to_le_bytes())?;
+            w.write_all(&entry.score.get().to_le_bytes())?;
+            w.write_all(&[entry.col.get()])?;
+        }
+        w.flush()
+    }
+
+    /// Reads a table written by [`SolvedTable::write_to`] with the same
+    /// `magic` and `version`, and its `param`. `what`, such as "opening
+    /// book", names the file in the errors.
+    pub(crate) fn read_from(
+        mut r: impl Read,
+        magic: &[u8; 8],
+        version: u8,
+        what: &str,
+    ) -> io::Result<(u8, Self)> {
+        let mut header = [0; HEADER_SIZE];
+        r.read_exact(&mut header)?;
+        if &header[..8] != magic {
+            return Err(invalid_data(&format!("{}: wrong magic string", what)));
+        }
+        if header[8] != version {
+            return Err(invalid_data(&format!("{}: unsupported version", what)));
+        }
+        let param = header[9];
+        let len = u64::from_le_bytes(header[10..].try_into().unwrap());
+        // Not `with_capacity(len)`: a corrupt header must not allocate.
+        let mut entries = Vec::new();
+        let mut buf = [0; ENTRY_SIZE];
+        for _ in 0..len {
+            r.read_exact(&mut buf)?;
+            let key = u64::from_le_bytes(buf[..8].try_into().unwrap());
+            let score = Score::from_raw(i16::from_le_bytes(buf[8..10].try_into().unwrap()))
+                .ok_or_else(|| invalid_data("score out of range"))?;
+            let col = ColIdx::new(buf[10]).ok_or_else(|| invalid_data("invalid column"))?;
+            entries.push(Entry { key, score, col });
+        }
+        if !entries.windows(2).all(|w| w[0].key < w[1].key) {
+            return Err(invalid_data(&format!("{}: entries are not sorted", what)));
+        }
+        Ok((param, Self { entries }))
+    }
+}
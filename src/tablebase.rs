@@ -0,0 +1,262 @@
+//! Endgame tablebase: the exact value and a best move for every position
+//! with few empty cells after a given one, computed backwards from the end
+//! of the game rather than searched. Every legal position with few empty
+//! cells would be far too many, so a tablebase covers the endgame of a
+//! single position.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use ahash::AHashMap;
+
+use crate::ai::Score;
+use crate::packedboard::*;
+use crate::solved::SolvedTable;
+
+const MAGIC: &[u8; 8] = b"F4RTBASE";
+const VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tablebase {
+    max_empty: u8,
+    positions: SolvedTable,
+}
+
+/// Most positions [`Tablebase::generate`] enumerates by default, on the way
+/// to the tablebase or in it: about 200 MB of boards at a time.
+pub const MAX_POSITIONS: usize = 20_000_000;
+
+/// [`Tablebase::generate`] gave up: more than `limit` positions lie between
+/// its root and the end of the game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TooManyPositions {
+    pub limit: usize,
+    /// Empty cells of the positions that could not be enumerated.
+    pub empty: u8,
+}
+
+impl fmt::Display for TooManyPositions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "More than {} positions to enumerate down to {} empty cells: start from a position with fewer empty cells",
+            self.limit, self.empty
+        )
+    }
+}
+
+impl std::error::Error for TooManyPositions {}
+
+impl Tablebase {
+    /// Computes every position reachable from `root` that is not already
+    /// won and has between 1 and `max_empty` empty cells.
+    ///
+    /// The positions are enumerated forwards, every position between `root`
+    /// and them included, then valued backwards, those with the fewest empty
+    /// cells first, each from the values of its children. Only the positions
+    /// with at most `max_empty` empty cells and the level being enumerated
+    /// are kept, but their number grows very fast with the number of tokens
+    /// after `root`: from the empty board, there are billions of positions
+    /// with a single empty cell. So the enumeration gives up once it could
+    /// meet more than `max_positions` positions, before allocating them:
+    /// `root` has to be close enough to the end of the game, whatever
+    /// `max_empty`.
+    ///
+    /// `progress` is called with the number of empty cells of each batch
+    /// before it is valued, and the number of positions in it.
+    pub fn generate(
+        root: &Board,
+        max_empty: u8,
+        max_positions: usize,
+        mut progress: impl FnMut(u8, usize),
+    ) -> Result<Self, TooManyPositions> {
+        let empty = |b: &Board| GRID_SIZE - b.occupancy();
+        let mut level = vec![root.clone()];
+        let mut levels = Vec::new();
+        let mut enumerated = 1;
+        while !level.is_empty() && empty(&level[0]) > 0 {
+            // Each position has at most one child by column.
+            if enumerated + level.len() * ALL_COL_IDXS.len() > max_positions {
+                return Err(TooManyPositions {
+                    limit: max_positions,
+                    empty: empty(&level[0]) - 1,
+                });
+            }
+            let next = next_positions(&level);
+            enumerated += next.len();
+            if empty(&level[0]) <= max_empty {
+                levels.push(level);
+            }
+            level = next;
+        }
+        let mut solved = Vec::new();
+        // Values of the positions with one token more than those being valued.
+        let mut children = AHashMap::new();
+        for level in levels.iter().rev() {
+            progress(empty(&level[0]), level.len());
+            let mut values = AHashMap::with_capacity(level.len());
+            for b in level {
+                let (col, score) = best_move(b, &children);
+                values.insert(b.canonical_key().0, score);
+                solved.push((b.clone(), col, score));
+            }
+            children = values;
+        }
+        Ok(Self {
+            max_empty,
+            positions: SolvedTable::new(solved),
+        })
+    }
+
+    pub fn max_empty(&self) -> u8 {
+        self.max_empty
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Best move and exact value of `b`, if it is in the tablebase.
+    pub fn lookup(&self, b: &Board) -> Option<(ColIdx, Score)> {
+        if GRID_SIZE - b.occupancy() > self.max_empty {
+            return None;
+        }
+        self.positions.lookup(b)
+    }
+
+    /// Writes the tablebase: a header made of a magic string, the format
+    /// version, the maximum number of empty cells and the number of
+    /// entries, then 11 bytes per entry.
+    pub fn write_to(&self, w: impl Write) -> io::Result<()> {
+        self.positions.write_to(w, MAGIC, VERSION, self.max_empty)
+    }
+
+    pub fn read_from(r: impl Read) -> io::Result<Self> {
+        let (max_empty, positions) = SolvedTable::read_from(r, MAGIC, VERSION, "tablebase")?;
+        Ok(Self {
+            max_empty,
+            positions,
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+/// Best move and exact value of `b`, the values of its children that are
+/// neither won nor full being in `children`.
+fn best_move(b: &Board, children: &AHashMap<u64, Score>) -> (ColIdx, Score) {
+    let turn = b.occupancy() + 1;
+    let mut best = None;
+    for &coli in &ALL_COL_IDXS {
+        let mut child = b.clone();
+        let score = match child.add_and_check(coli, b.to_play()) {
+            Err(_) => continue,
+            Ok(true) => Score::win(22 - turn.div_ceil(2) as i8),
+            Ok(false) if child.occupancy() == GRID_SIZE => Score::new(0),
+            Ok(false) => -children[&child.canonical_key().0],
+        };
+        if best.is_none_or(|(_, best_score)| score > best_score) {
+            best = Some((coli, score));
+        }
+    }
+    best.expect("positions in a tablebase are not full")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::AI;
+    use crate::solved::{ENTRY_SIZE, HEADER_SIZE};
+    use ahash::AHashSet;
+
+    const ROOT: &str = "225257625346224411156336534367";
+
+    #[test]
+    fn agrees_with_the_solver() {
+        let root = Board::from_moves(ROOT).unwrap();
+        let tablebase = Tablebase::generate(&root, 6, MAX_POSITIONS, |_, _| {}).unwrap();
+        assert!(tablebase.len() > 1);
+        assert!(tablebase.lookup(&root).is_none());
+        let mut ai = AI::new();
+        let mut seen = AHashSet::new();
+        let mut stack = vec![root];
+        while let Some(b) = stack.pop() {
+            for &coli in &ALL_COL_IDXS {
+                let mut child = b.clone();
+                if let Ok(false) = child.add_and_check(coli, b.to_play()) {
+                    if child.occupancy() < GRID_SIZE && seen.insert(child.key()) {
+                        stack.push(child);
+                    }
+                }
+            }
+            if GRID_SIZE - b.occupancy() <= 6 {
+                for b in &[b.clone(), b.mirrored()] {
+                    let (col, score) = tablebase.lookup(b).unwrap();
+                    assert_eq!(score, ai.solve(b).unwrap().score);
+                    let mut child = b.clone();
+                    let won = child.add_and_check(col, b.to_play()).unwrap();
+                    if !won && child.occupancy() < GRID_SIZE {
+                        assert_eq!(-ai.solve(&child).unwrap().score, score);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn spares_the_search() {
+        let root = Board::from_moves("231634161247672231544674712724").unwrap();
+        let tablebase = Tablebase::generate(&root, 8, MAX_POSITIONS, |_, _| {}).unwrap();
+        let with = AI::new().with_tablebase(tablebase).think(&root).unwrap();
+        let without = AI::new().think(&root).unwrap();
+        assert_eq!(with.score, without.score);
+        assert!(with.nodes < without.nodes);
+    }
+
+    #[test]
+    fn round_trip() {
+        let root = Board::from_moves(ROOT).unwrap();
+        let tablebase = Tablebase::generate(&root, 4, MAX_POSITIONS, |_, _| {}).unwrap();
+        let mut bytes = Vec::new();
+        tablebase.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), HEADER_SIZE + ENTRY_SIZE * tablebase.len());
+        assert_eq!(Tablebase::read_from(&bytes[..]).unwrap(), tablebase);
+        bytes[0] = b'X';
+        assert!(Tablebase::read_from(&bytes[..]).is_err());
+    }
+
+    #[test]
+    fn corrupt_header() {
+        let root = Board::from_moves(ROOT).unwrap();
+        let mut bytes = Vec::new();
+        Tablebase::generate(&root, 2, MAX_POSITIONS, |_, _| {})
+            .unwrap()
+            .write_to(&mut bytes)
+            .unwrap();
+        bytes[10..HEADER_SIZE].copy_from_slice(&u64::MAX.to_le_bytes());
+        let err = Tablebase::read_from(&bytes[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(Tablebase::read_from(&bytes[..12]).is_err());
+    }
+
+    #[test]
+    fn gives_up_far_from_the_end() {
+        let err = Tablebase::generate(&Board::new(), 2, 100_000, |_, _| {}).unwrap_err();
+        assert_eq!(err.limit, 100_000);
+        assert!(err.empty > 2);
+        let root = Board::from_moves(ROOT).unwrap();
+        assert!(Tablebase::generate(&root, 2, 1000, |_, _| {}).is_err());
+    }
+}